@@ -4,6 +4,8 @@ use std::io;
 
 use thiserror::Error;
 
+use crate::mdf::MdfCompression;
+
 /// Error returned when opening (reading) an MDF file fails.
 #[derive(Debug, Error)]
 pub enum MdfOpenError {
@@ -11,6 +13,14 @@ pub enum MdfOpenError {
     #[error("invalid mdf signature")]
     InvalidSignature,
 
+    /// The MDF body does not begin with a known compression signature.
+    #[error("unknown mdf compression")]
+    UnknownCompression,
+
+    /// The MDF body uses a codec which is not enabled in this build.
+    #[error("unsupported mdf compression: {0:?}")]
+    UnsupportedCompression(MdfCompression),
+
     /// An I/O error occurred while reading the stream.
     #[error(transparent)]
     Io(#[from] io::Error),
@@ -19,6 +29,14 @@ pub enum MdfOpenError {
 /// Error returned when creating (writing) an MDF file fails.
 #[derive(Debug, Error)]
 pub enum MdfCreateError {
+    /// An [`MdfOptions`](crate::mdf::MdfOptions) value is out of range.
+    #[error("invalid mdf option: {0}")]
+    InvalidOption(&'static str),
+
+    /// The requested codec is not enabled in this build.
+    #[error("unsupported mdf compression: {0:?}")]
+    UnsupportedCompression(MdfCompression),
+
     /// An I/O error occurred while writing the MDF header.
     #[error("failed to write header")]
     Header(
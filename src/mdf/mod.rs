@@ -2,21 +2,85 @@
 
 pub mod error;
 
-use std::io::{self, BufRead, Read, Seek, SeekFrom, Take, Write};
+mod options;
+mod zlib;
+
+pub use options::MdfOptions;
+#[cfg(feature = "zlib-rs")]
+pub use options::MdfStrategy;
+
+use std::io::{self, BufRead, Chain, Cursor, Read, Seek, SeekFrom, Take, Write};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use flate2::{Compression, bufread::ZlibDecoder, write::ZlibEncoder};
+use flate2::bufread::ZlibDecoder;
 
 use crate::{
-    PSB_MDF_SIGNATURE,
-    mdf::error::{MdfCreateError, MdfOpenError},
+    PSB_MDF_SIGNATURE, PSB_SIGNATURE,
+    mdf::{
+        error::{MdfCreateError, MdfOpenError},
+        zlib::{ParallelZlibWriter, ZlibWriter},
+    },
 };
 
-/// A streaming reader for MDF (zlib-compressed PSB) files.
+#[cfg(feature = "zlib-rs")]
+use crate::mdf::zlib::DictionaryZlibDecoder;
+
+/// LZ4 frame magic number (little-endian `u32`).
+pub const LZ4_FRAME_SIGNATURE: u32 = 0x184D2204;
+
+/// Compression codec used for the body of an MDF file.
+///
+/// The codec is not stored in the MDF header; [`MdfReader`] detects it from the
+/// signature at the start of the body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MdfCompression {
+    /// zlib stream, as produced by the official E-mote tools.
+    #[default]
+    Zlib,
+    /// LZ4 frame, used by some newer E-mote builds.
+    ///
+    /// Requires the `lz4` feature.
+    Lz4,
+    /// Uncompressed PSB data, as shipped by debug builds.
+    Stored,
+}
+
+impl MdfCompression {
+    /// Detects the codec from the first bytes of an MDF body.
+    ///
+    /// Returns `None` if `data` does not start with a known signature.
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        let signature = u32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+        if signature == LZ4_FRAME_SIGNATURE {
+            return Some(Self::Lz4);
+        } else if signature == PSB_SIGNATURE {
+            return Some(Self::Stored);
+        }
+
+        // zlib header: deflate method with a valid FCHECK
+        let (cmf, flg) = (data[0], data[1]);
+        if cmf & 0x0f == 8 && cmf >> 4 <= 7 && (u16::from(cmf) << 8 | u16::from(flg)) % 31 == 0 {
+            Some(Self::Zlib)
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if this codec is available in the current build.
+    #[inline]
+    pub const fn is_supported(self) -> bool {
+        match self {
+            Self::Zlib | Self::Stored => true,
+            Self::Lz4 => cfg!(feature = "lz4"),
+        }
+    }
+}
+
+/// A streaming reader for MDF (compressed PSB) files.
 ///
 /// MDF files consist of an 8-byte header (signature + compressed-data length)
-/// followed by the zlib-compressed PSB data. [`MdfReader`] transparently
-/// decompresses the data as it is read.
+/// followed by the compressed PSB data. [`MdfReader`] detects the
+/// [`MdfCompression`] codec and transparently decompresses the data as it is read.
 ///
 /// # Example
 ///
@@ -29,24 +93,66 @@ use crate::{
 /// let mut buf = Vec::new();
 /// reader.read_to_end(&mut buf).unwrap();
 /// ```
-pub struct MdfReader<T> {
-    inner: ZlibDecoder<Take<T>>,
+pub struct MdfReader<T: BufRead> {
+    inner: MdfDecoder<T>,
     size: u32,
 }
 
 impl<T: BufRead> MdfReader<T> {
     /// Open new mdf stream
-    pub fn open(mut stream: T) -> Result<Self, MdfOpenError> {
+    pub fn open(stream: T) -> Result<Self, MdfOpenError> {
+        let (compression, stream, size) = Self::open_body(stream)?;
+        let inner = match compression {
+            MdfCompression::Zlib => MdfDecoder::Zlib(ZlibDecoder::new(stream)),
+            #[cfg(feature = "lz4")]
+            MdfCompression::Lz4 => MdfDecoder::Lz4(lz4_flex::frame::FrameDecoder::new(stream)),
+            MdfCompression::Stored => MdfDecoder::Stored(stream),
+            #[allow(unreachable_patterns)]
+            compression => return Err(MdfOpenError::UnsupportedCompression(compression)),
+        };
+
+        Ok(Self { inner, size })
+    }
+
+    /// Opens an mdf stream whose zlib body may be primed with the preset
+    /// `dictionary`, as written with [`MdfOptions::dictionary`].
+    ///
+    /// Bodies of other codecs are read as with [`MdfReader::open`].
+    #[cfg(feature = "zlib-rs")]
+    pub fn open_with_dictionary(
+        stream: T,
+        dictionary: impl Into<Vec<u8>>,
+    ) -> Result<Self, MdfOpenError> {
+        let (compression, stream, size) = Self::open_body(stream)?;
+        let inner = match compression {
+            MdfCompression::Zlib => {
+                MdfDecoder::DictionaryZlib(DictionaryZlibDecoder::new(stream, dictionary.into()))
+            }
+            #[cfg(feature = "lz4")]
+            MdfCompression::Lz4 => MdfDecoder::Lz4(lz4_flex::frame::FrameDecoder::new(stream)),
+            MdfCompression::Stored => MdfDecoder::Stored(stream),
+            #[allow(unreachable_patterns)]
+            compression => return Err(MdfOpenError::UnsupportedCompression(compression)),
+        };
+
+        Ok(Self { inner, size })
+    }
+
+    /// Reads the mdf header, returning the compression and the body of the stream.
+    fn open_body(mut stream: T) -> Result<(MdfCompression, MdfBody<T>, u32), MdfOpenError> {
         let signature = stream.read_u32::<LittleEndian>()?;
         if signature != PSB_MDF_SIGNATURE {
             return Err(MdfOpenError::InvalidSignature);
         }
 
         let size = stream.read_u32::<LittleEndian>()?;
-        Ok(Self {
-            inner: ZlibDecoder::new(stream.take(size as _)),
-            size,
-        })
+        // The buffer may hold fewer bytes than the codec signature, so it is read
+        // and put back in front of the body.
+        let mut head = Vec::with_capacity(4);
+        stream.by_ref().take(4).read_to_end(&mut head)?;
+        let compression = MdfCompression::detect(&head).ok_or(MdfOpenError::UnknownCompression)?;
+        let stream = Cursor::new(head).chain(stream).take(size as _);
+        Ok((compression, stream, size))
     }
 
     /// Returns total size of mdf data stream
@@ -54,28 +160,60 @@ impl<T: BufRead> MdfReader<T> {
     pub const fn size(&self) -> u32 {
         self.size
     }
+
+    /// Returns the compression codec of the mdf data stream
+    #[inline]
+    pub const fn compression(&self) -> MdfCompression {
+        match self.inner {
+            MdfDecoder::Zlib(_) => MdfCompression::Zlib,
+            #[cfg(feature = "zlib-rs")]
+            MdfDecoder::DictionaryZlib(_) => MdfCompression::Zlib,
+            #[cfg(feature = "lz4")]
+            MdfDecoder::Lz4(_) => MdfCompression::Lz4,
+            MdfDecoder::Stored(_) => MdfCompression::Stored,
+        }
+    }
 }
 
 impl<T: BufRead> Read for MdfReader<T> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.inner.read(buf)
+        match &mut self.inner {
+            MdfDecoder::Zlib(inner) => inner.read(buf),
+            #[cfg(feature = "zlib-rs")]
+            MdfDecoder::DictionaryZlib(inner) => inner.read(buf),
+            #[cfg(feature = "lz4")]
+            MdfDecoder::Lz4(inner) => inner.read(buf),
+            MdfDecoder::Stored(inner) => inner.read(buf),
+        }
     }
 }
 
-/// A streaming writer for MDF (zlib-compressed PSB) files.
+/// Body of an MDF stream, starting with the codec signature read on open.
+type MdfBody<T> = Take<Chain<Cursor<Vec<u8>>, T>>;
+
+enum MdfDecoder<T: BufRead> {
+    Zlib(ZlibDecoder<MdfBody<T>>),
+    #[cfg(feature = "zlib-rs")]
+    DictionaryZlib(DictionaryZlibDecoder<MdfBody<T>>),
+    #[cfg(feature = "lz4")]
+    Lz4(lz4_flex::frame::FrameDecoder<MdfBody<T>>),
+    Stored(MdfBody<T>),
+}
+
+/// A streaming writer for MDF (compressed PSB) files.
 ///
 /// Write the PSB data to this writer as if it were a normal [`Write`] sink; call
-/// [`finish`] when done to flush the zlib stream and back-fill the compressed-data
-/// length field in the header.
+/// [`finish`] when done to flush the compressed stream and back-fill the
+/// compressed-data length field in the header.
 ///
 /// [`finish`]: MdfWriter::finish
 pub struct MdfWriter<T: Write> {
-    inner: ZlibEncoder<T>,
+    inner: MdfEncoder<T>,
     stream_start: u64,
 }
 
 impl<T: Write + Seek> MdfWriter<T> {
-    /// Creates a new [`MdfWriter`], writing the MDF header to `stream`.
+    /// Creates a new zlib [`MdfWriter`], writing the MDF header to `stream`.
     ///
     /// - `stream` — writable, seekable output stream.
     /// - `level` — zlib compression level (0 = no compression, 9 = maximum).
@@ -83,25 +221,85 @@ impl<T: Write + Seek> MdfWriter<T> {
     /// # Errors
     ///
     /// Returns [`MdfCreateError`] if writing the header fails.
-    pub fn new(mut stream: T, level: u8) -> Result<Self, MdfCreateError> {
+    pub fn new(stream: T, level: u8) -> Result<Self, MdfCreateError> {
+        Self::with_options(stream, &MdfOptions::new().level(level))
+    }
+
+    /// Creates a new [`MdfWriter`] using the given `compression` codec, writing the
+    /// MDF header to `stream`.
+    ///
+    /// zlib data is compressed with the default compression level.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MdfCreateError::UnsupportedCompression`] if the codec is not
+    /// available in this build, or [`MdfCreateError`] if writing the header fails.
+    pub fn with_compression(
+        stream: T,
+        compression: MdfCompression,
+    ) -> Result<Self, MdfCreateError> {
+        Self::with_options(stream, &MdfOptions::new().compression(compression))
+    }
+
+    /// Creates a new [`MdfWriter`] configured by `options`, writing the MDF header
+    /// to `stream`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MdfCreateError::InvalidOption`] if an option is out of range,
+    /// [`MdfCreateError::UnsupportedCompression`] if the codec is not available in
+    /// this build, or [`MdfCreateError`] if writing the header fails.
+    pub fn with_options(mut stream: T, options: &MdfOptions) -> Result<Self, MdfCreateError> {
+        options.validate()?;
+        if !options.compression.is_supported() {
+            return Err(MdfCreateError::UnsupportedCompression(options.compression));
+        }
+
         // Write header
         stream.write_u32::<LittleEndian>(PSB_MDF_SIGNATURE)?;
         // Fill with zero for now
         stream.write_u32::<LittleEndian>(0)?;
         let stream_start = stream.stream_position()?;
+
+        let inner = match options.compression {
+            MdfCompression::Zlib if options.thread_count() > 1 => {
+                MdfEncoder::ParallelZlib(ParallelZlibWriter::new(stream, options)?)
+            }
+            MdfCompression::Zlib => MdfEncoder::Zlib(ZlibWriter::new(stream, options)?),
+            #[cfg(feature = "lz4")]
+            MdfCompression::Lz4 => MdfEncoder::Lz4(lz4_flex::frame::FrameEncoder::new(stream)),
+            MdfCompression::Stored => MdfEncoder::Stored(stream),
+            #[allow(unreachable_patterns)]
+            compression => return Err(MdfCreateError::UnsupportedCompression(compression)),
+        };
+
         Ok(Self {
-            inner: ZlibEncoder::new(stream, Compression::new(level as _)),
+            inner,
             stream_start,
         })
     }
 
     /// Finish mdf file
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] if writing fails, or of kind
+    /// [`FileTooLarge`](io::ErrorKind::FileTooLarge) if the body exceeds 4 GiB.
     pub fn finish(self) -> io::Result<T> {
-        let mut stream = self.inner.finish()?;
+        let mut stream = match self.inner {
+            MdfEncoder::Zlib(inner) => inner.finish()?,
+            MdfEncoder::ParallelZlib(inner) => inner.finish()?,
+            #[cfg(feature = "lz4")]
+            MdfEncoder::Lz4(inner) => inner.finish()?,
+            MdfEncoder::Stored(inner) => inner,
+        };
 
         let end = stream.stream_position()?;
+        let len = u32::try_from(end - self.stream_start).map_err(|_| {
+            io::Error::new(io::ErrorKind::FileTooLarge, "mdf body exceeds u32 limit")
+        })?;
         stream.seek(SeekFrom::Start(self.stream_start - 4))?;
-        stream.write_u32::<LittleEndian>((end - self.stream_start) as u32)?;
+        stream.write_u32::<LittleEndian>(len)?;
         stream.seek(SeekFrom::Start(end))?;
         Ok(stream)
     }
@@ -109,10 +307,30 @@ impl<T: Write + Seek> MdfWriter<T> {
 
 impl<T: Write> Write for MdfWriter<T> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.inner.write(buf)
+        match &mut self.inner {
+            MdfEncoder::Zlib(inner) => inner.write(buf),
+            MdfEncoder::ParallelZlib(inner) => inner.write(buf),
+            #[cfg(feature = "lz4")]
+            MdfEncoder::Lz4(inner) => inner.write(buf),
+            MdfEncoder::Stored(inner) => inner.write(buf),
+        }
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        self.inner.flush()
+        match &mut self.inner {
+            MdfEncoder::Zlib(inner) => inner.flush(),
+            MdfEncoder::ParallelZlib(inner) => inner.flush(),
+            #[cfg(feature = "lz4")]
+            MdfEncoder::Lz4(inner) => inner.flush(),
+            MdfEncoder::Stored(inner) => inner.flush(),
+        }
     }
 }
+
+enum MdfEncoder<T: Write> {
+    Zlib(ZlibWriter<T>),
+    ParallelZlib(ParallelZlibWriter<T>),
+    #[cfg(feature = "lz4")]
+    Lz4(lz4_flex::frame::FrameEncoder<T>),
+    Stored(T),
+}
@@ -0,0 +1,173 @@
+use crate::mdf::{MdfCompression, error::MdfCreateError};
+
+/// Options for creating an MDF file with [`MdfWriter::with_options`].
+///
+/// Options are set with builder methods:
+///
+/// ```
+/// use emote_psb::mdf::{MdfCompression, MdfOptions};
+///
+/// let options = MdfOptions::new()
+///     .compression(MdfCompression::Zlib)
+///     .level(9)
+///     .threads(4);
+/// ```
+///
+/// Window bits, compression strategy and preset dictionary require the `zlib-rs`
+/// feature.
+///
+/// [`MdfWriter::with_options`]: crate::mdf::MdfWriter::with_options
+#[derive(Debug, Clone)]
+pub struct MdfOptions {
+    pub(crate) compression: MdfCompression,
+    pub(crate) level: u8,
+    pub(crate) window_bits: u8,
+    #[cfg(feature = "zlib-rs")]
+    pub(crate) strategy: MdfStrategy,
+    #[cfg(feature = "zlib-rs")]
+    pub(crate) dictionary: Option<Vec<u8>>,
+    pub(crate) threads: usize,
+    pub(crate) block_size: usize,
+}
+
+impl MdfOptions {
+    /// Default zlib compression level.
+    pub const DEFAULT_LEVEL: u8 = 6;
+
+    /// Default (and maximum) zlib window size in bits.
+    pub const DEFAULT_WINDOW_BITS: u8 = 15;
+
+    /// Default size of an independently compressed block when compressing on
+    /// multiple threads.
+    pub const DEFAULT_BLOCK_SIZE: usize = 256 * 1024;
+
+    /// Creates options for a single-threaded zlib MDF with the default level.
+    pub const fn new() -> Self {
+        Self {
+            compression: MdfCompression::Zlib,
+            level: Self::DEFAULT_LEVEL,
+            window_bits: Self::DEFAULT_WINDOW_BITS,
+            #[cfg(feature = "zlib-rs")]
+            strategy: MdfStrategy::Default,
+            #[cfg(feature = "zlib-rs")]
+            dictionary: None,
+            threads: 1,
+            block_size: Self::DEFAULT_BLOCK_SIZE,
+        }
+    }
+
+    /// Sets the compression codec of the MDF body.
+    ///
+    /// Every other option only applies to [`MdfCompression::Zlib`].
+    pub const fn compression(mut self, compression: MdfCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the zlib compression level (0 = no compression, 9 = maximum).
+    pub const fn level(mut self, level: u8) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Sets the base two logarithm of the zlib window size (9 to 15).
+    ///
+    /// Smaller windows need less memory to decompress at the cost of compression ratio.
+    #[cfg(feature = "zlib-rs")]
+    pub const fn window_bits(mut self, window_bits: u8) -> Self {
+        self.window_bits = window_bits;
+        self
+    }
+
+    /// Sets the zlib compression strategy.
+    #[cfg(feature = "zlib-rs")]
+    pub const fn strategy(mut self, strategy: MdfStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Sets a preset dictionary.
+    ///
+    /// The dictionary id is stored in the zlib header, and the same dictionary must
+    /// be supplied to decompress the body, e.g. with
+    /// [`MdfReader::open_with_dictionary`](crate::mdf::MdfReader::open_with_dictionary).
+    #[cfg(feature = "zlib-rs")]
+    pub fn dictionary(mut self, dictionary: impl Into<Vec<u8>>) -> Self {
+        self.dictionary = Some(dictionary.into());
+        self
+    }
+
+    /// Sets the number of compression threads.
+    ///
+    /// With more than one thread, the body is split into blocks of
+    /// [`block_size`](MdfOptions::block_size) bytes which are deflated
+    /// independently and joined into a single zlib stream.
+    /// `0` uses the available parallelism of the system.
+    pub const fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Sets the size of a block compressed by one thread.
+    pub const fn block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), MdfCreateError> {
+        if self.level > 9 {
+            return Err(MdfCreateError::InvalidOption("level"));
+        }
+
+        if !(9..=15).contains(&self.window_bits) {
+            return Err(MdfCreateError::InvalidOption("window_bits"));
+        }
+
+        if self.block_size == 0 {
+            return Err(MdfCreateError::InvalidOption("block_size"));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn preset_dictionary(&self) -> &[u8] {
+        #[cfg(feature = "zlib-rs")]
+        if let Some(ref dictionary) = self.dictionary {
+            return dictionary;
+        }
+
+        &[]
+    }
+
+    pub(crate) fn thread_count(&self) -> usize {
+        match self.threads {
+            0 => std::thread::available_parallelism().map_or(1, Into::into),
+            threads => threads,
+        }
+    }
+}
+
+impl Default for MdfOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// zlib compression strategy.
+///
+/// Requires the `zlib-rs` feature.
+#[cfg(feature = "zlib-rs")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MdfStrategy {
+    /// Normal deflate compression.
+    #[default]
+    Default,
+    /// Tuned for data produced by a filter or predictor (small values with random distribution).
+    Filtered,
+    /// Huffman coding only, without string matching.
+    HuffmanOnly,
+    /// Limits match distances to one (run-length encoding).
+    Rle,
+    /// Prevents the use of dynamic Huffman codes.
+    Fixed,
+}
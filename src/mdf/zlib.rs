@@ -0,0 +1,428 @@
+//! zlib encoders used by [`MdfWriter`](crate::mdf::MdfWriter), and the preset
+//! dictionary decoder used by [`MdfReader`](crate::mdf::MdfReader).
+
+use std::{
+    io::{self, Write},
+    thread,
+};
+
+use adler2::Adler32;
+
+use crate::mdf::MdfOptions;
+
+/// Output reserved for each call into the compressor.
+const CHUNK_SIZE: usize = 32 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flush {
+    None,
+    Sync,
+    Finish,
+}
+
+/// Deflate compressor over the enabled zlib backend.
+pub struct Deflater {
+    #[cfg(not(feature = "zlib-rs"))]
+    inner: flate2::Compress,
+    #[cfg(feature = "zlib-rs")]
+    inner: zlib_rs::Deflate,
+}
+
+impl Deflater {
+    /// Creates a compressor primed with `dictionary`, producing a zlib stream if
+    /// `zlib_header` is set or a raw deflate stream otherwise.
+    #[cfg(not(feature = "zlib-rs"))]
+    pub fn new(options: &MdfOptions, zlib_header: bool, dictionary: &[u8]) -> io::Result<Self> {
+        if !dictionary.is_empty() {
+            return Err(io::ErrorKind::Unsupported.into());
+        }
+
+        Ok(Self {
+            inner: flate2::Compress::new(flate2::Compression::new(options.level as _), zlib_header),
+        })
+    }
+
+    /// Creates a compressor primed with `dictionary`, producing a zlib stream if
+    /// `zlib_header` is set or a raw deflate stream otherwise.
+    #[cfg(feature = "zlib-rs")]
+    pub fn new(options: &MdfOptions, zlib_header: bool, dictionary: &[u8]) -> io::Result<Self> {
+        use crate::mdf::MdfStrategy;
+
+        let window_bits = i32::from(options.window_bits);
+        let mut inner = zlib_rs::Deflate::new_with_config(zlib_rs::DeflateConfig {
+            level: options.level as _,
+            window_bits: if zlib_header {
+                window_bits
+            } else {
+                -window_bits
+            },
+            strategy: match options.strategy {
+                MdfStrategy::Default => zlib_rs::Strategy::Default,
+                MdfStrategy::Filtered => zlib_rs::Strategy::Filtered,
+                MdfStrategy::HuffmanOnly => zlib_rs::Strategy::HuffmanOnly,
+                MdfStrategy::Rle => zlib_rs::Strategy::Rle,
+                MdfStrategy::Fixed => zlib_rs::Strategy::Fixed,
+            },
+            ..Default::default()
+        });
+        if !dictionary.is_empty() {
+            inner
+                .set_dictionary(dictionary)
+                .map_err(|err| io::Error::other(err.as_str()))?;
+        }
+
+        Ok(Self { inner })
+    }
+
+    /// Compresses the whole `input`, appending compressed bytes to `out`.
+    pub fn compress(
+        &mut self,
+        mut input: &[u8],
+        out: &mut Vec<u8>,
+        flush: Flush,
+    ) -> io::Result<()> {
+        loop {
+            let (read, written, stream_end) = self.compress_chunk(input, out, flush)?;
+            input = &input[read..];
+
+            let done = match flush {
+                Flush::None => input.is_empty(),
+                Flush::Sync => input.is_empty() && written < CHUNK_SIZE,
+                Flush::Finish => stream_end,
+            };
+            if done {
+                return Ok(());
+            }
+        }
+    }
+
+    #[cfg(not(feature = "zlib-rs"))]
+    fn compress_chunk(
+        &mut self,
+        input: &[u8],
+        out: &mut Vec<u8>,
+        flush: Flush,
+    ) -> io::Result<(usize, usize, bool)> {
+        let flush = match flush {
+            Flush::None => flate2::FlushCompress::None,
+            Flush::Sync => flate2::FlushCompress::Sync,
+            Flush::Finish => flate2::FlushCompress::Finish,
+        };
+
+        out.reserve(CHUNK_SIZE);
+        let (total_in, out_len) = (self.inner.total_in(), out.len());
+        let status = self
+            .inner
+            .compress_vec(input, out, flush)
+            .map_err(io::Error::other)?;
+
+        Ok((
+            (self.inner.total_in() - total_in) as usize,
+            out.len() - out_len,
+            status == flate2::Status::StreamEnd,
+        ))
+    }
+
+    #[cfg(feature = "zlib-rs")]
+    fn compress_chunk(
+        &mut self,
+        input: &[u8],
+        out: &mut Vec<u8>,
+        flush: Flush,
+    ) -> io::Result<(usize, usize, bool)> {
+        let flush = match flush {
+            Flush::None => zlib_rs::DeflateFlush::NoFlush,
+            Flush::Sync => zlib_rs::DeflateFlush::SyncFlush,
+            Flush::Finish => zlib_rs::DeflateFlush::Finish,
+        };
+
+        let (total_in, total_out, out_len) =
+            (self.inner.total_in(), self.inner.total_out(), out.len());
+        out.resize(out_len + CHUNK_SIZE, 0);
+        let status = self
+            .inner
+            .compress(input, &mut out[out_len..], flush)
+            .map_err(|err| io::Error::other(err.as_str()))?;
+        let written = (self.inner.total_out() - total_out) as usize;
+        out.truncate(out_len + written);
+
+        Ok((
+            (self.inner.total_in() - total_in) as usize,
+            written,
+            status == zlib_rs::Status::StreamEnd,
+        ))
+    }
+}
+
+/// Single-threaded zlib stream writer.
+pub struct ZlibWriter<T> {
+    deflater: Deflater,
+    buf: Vec<u8>,
+    inner: T,
+}
+
+impl<T: Write> ZlibWriter<T> {
+    pub fn new(inner: T, options: &MdfOptions) -> io::Result<Self> {
+        Ok(Self {
+            deflater: Deflater::new(options, true, options.preset_dictionary())?,
+            buf: Vec::with_capacity(CHUNK_SIZE),
+            inner,
+        })
+    }
+
+    pub fn finish(mut self) -> io::Result<T> {
+        self.deflater.compress(&[], &mut self.buf, Flush::Finish)?;
+        self.inner.write_all(&self.buf)?;
+        Ok(self.inner)
+    }
+}
+
+impl<T: Write> Write for ZlibWriter<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.deflater.compress(buf, &mut self.buf, Flush::None)?;
+        self.inner.write_all(&self.buf)?;
+        self.buf.clear();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.deflater.compress(&[], &mut self.buf, Flush::Sync)?;
+        self.inner.write_all(&self.buf)?;
+        self.buf.clear();
+        self.inner.flush()
+    }
+}
+
+/// Multi-threaded zlib stream writer.
+///
+/// Input is split into fixed size blocks. Each block is deflated on its own thread
+/// into a byte-aligned raw deflate stream, and the results are concatenated between
+/// a zlib header and the Adler-32 checksum of the whole input.
+pub struct ParallelZlibWriter<T> {
+    options: MdfOptions,
+    threads: usize,
+    pending: Vec<u8>,
+    /// Input preceding the pending data, primed as dictionary of the next block
+    window: Vec<u8>,
+    adler: Adler32,
+    inner: T,
+}
+
+impl<T: Write> ParallelZlibWriter<T> {
+    pub fn new(mut inner: T, options: &MdfOptions) -> io::Result<Self> {
+        inner.write_all(&zlib_header(options))?;
+
+        Ok(Self {
+            options: options.clone(),
+            threads: options.thread_count(),
+            pending: Vec::new(),
+            window: options.preset_dictionary().to_vec(),
+            adler: Adler32::new(),
+            inner,
+        })
+    }
+
+    pub fn finish(mut self) -> io::Result<T> {
+        self.compress_full_blocks()?;
+
+        let mut out = vec![];
+        Deflater::new(&self.options, false, &self.window)?.compress(
+            &self.pending,
+            &mut out,
+            Flush::Finish,
+        )?;
+        self.inner.write_all(&out)?;
+        self.inner.write_all(&self.adler.checksum().to_be_bytes())?;
+        Ok(self.inner)
+    }
+
+    /// Compresses and writes the pending blocks that are full.
+    fn compress_full_blocks(&mut self) -> io::Result<()> {
+        let full = self.pending.len() / self.options.block_size * self.options.block_size;
+        self.compress_blocks(full)
+    }
+
+    /// Compresses and writes the first `len` bytes of pending data, which must be a
+    /// multiple of the block size.
+    ///
+    /// Blocks are compressed in rounds of at most one block per thread.
+    fn compress_blocks(&mut self, len: usize) -> io::Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+
+        let options = &self.options;
+        let data = &self.pending[..len];
+        let batch = options.block_size * self.threads;
+        for (round, round_data) in data.chunks(batch).enumerate() {
+            let blocks = thread::scope(|scope| {
+                let handles = round_data
+                    .chunks(options.block_size)
+                    .enumerate()
+                    .map(|(i, block)| {
+                        let start = round * batch + i * options.block_size;
+                        let dictionary = if start == 0 {
+                            &self.window
+                        } else {
+                            window_of(options, &data[..start])
+                        };
+
+                        scope.spawn(move || {
+                            let mut out = vec![];
+                            Deflater::new(options, false, dictionary)?.compress(
+                                block,
+                                &mut out,
+                                Flush::Sync,
+                            )?;
+                            Ok::<_, io::Error>(out)
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().expect("compression thread panicked"))
+                    .collect::<io::Result<Vec<_>>>()
+            })?;
+
+            for block in blocks {
+                self.inner.write_all(&block)?;
+            }
+        }
+
+        let window = window_of(options, data).to_vec();
+        self.window = window;
+        self.pending.drain(..len);
+        Ok(())
+    }
+}
+
+impl<T: Write> Write for ParallelZlibWriter<T> {
+    /// Buffers input up to one block per thread, compressing the blocks once full.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let batch = self.options.block_size * self.threads;
+        let buf = &buf[..buf.len().min(batch - self.pending.len())];
+        self.adler.write_slice(buf);
+        self.pending.extend_from_slice(buf);
+
+        if self.pending.len() == batch {
+            self.compress_blocks(batch)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.compress_full_blocks()?;
+        self.inner.flush()
+    }
+}
+
+/// zlib stream reader priming a preset dictionary when the stream asks for one.
+#[cfg(feature = "zlib-rs")]
+pub struct DictionaryZlibDecoder<R> {
+    inflate: zlib_rs::Inflate,
+    dictionary: Vec<u8>,
+    done: bool,
+    inner: R,
+}
+
+#[cfg(feature = "zlib-rs")]
+impl<R> DictionaryZlibDecoder<R> {
+    pub fn new(inner: R, dictionary: Vec<u8>) -> Self {
+        Self {
+            inflate: zlib_rs::Inflate::new(true, 15),
+            dictionary,
+            done: false,
+            inner,
+        }
+    }
+}
+
+#[cfg(feature = "zlib-rs")]
+impl<R: io::BufRead> io::Read for DictionaryZlibDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let input = self.inner.fill_buf()?;
+            let flush = if input.is_empty() {
+                zlib_rs::InflateFlush::Finish
+            } else {
+                zlib_rs::InflateFlush::NoFlush
+            };
+
+            let (total_in, total_out) = (self.inflate.total_in(), self.inflate.total_out());
+            let res = self.inflate.decompress(input, buf, flush);
+            let read = (self.inflate.total_in() - total_in) as usize;
+            let written = (self.inflate.total_out() - total_out) as usize;
+            let ended = input.is_empty();
+            self.inner.consume(read);
+
+            match res {
+                Ok(status) => {
+                    if status == zlib_rs::Status::StreamEnd {
+                        self.done = true;
+                        return Ok(written);
+                    }
+                    if written > 0 {
+                        return Ok(written);
+                    }
+                    if ended {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                }
+                Err(zlib_rs::InflateError::NeedDict { .. }) => {
+                    self.inflate
+                        .set_dictionary(&self.dictionary)
+                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.as_str()))?;
+                }
+                Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err.as_str())),
+            }
+        }
+    }
+}
+
+/// Returns the tail of `data` that can be primed as dictionary of the following block.
+///
+/// Priming needs a zlib backend with dictionary support, so nothing is carried over
+/// without the `zlib-rs` feature.
+fn window_of<'a>(options: &MdfOptions, data: &'a [u8]) -> &'a [u8] {
+    if cfg!(feature = "zlib-rs") {
+        &data[data.len().saturating_sub(1 << options.window_bits)..]
+    } else {
+        &[]
+    }
+}
+
+/// Builds the zlib stream header for `options`, including the preset dictionary id.
+fn zlib_header(options: &MdfOptions) -> Vec<u8> {
+    let cmf = ((options.window_bits - 8) << 4) | 8;
+    let level = match options.level {
+        0..=1 => 0,
+        2..=5 => 1,
+        6 => 2,
+        _ => 3,
+    };
+    let mut flg = level << 6;
+
+    let dictionary = options.preset_dictionary();
+    let dictionary_id = (!dictionary.is_empty()).then(|| {
+        let mut adler = Adler32::new();
+        adler.write_slice(dictionary);
+        adler.checksum()
+    });
+
+    if dictionary_id.is_some() {
+        flg |= 0x20;
+    }
+    flg += 31 - ((u16::from(cmf) << 8 | u16::from(flg)) % 31) as u8;
+
+    let mut header = vec![cmf, flg];
+    if let Some(id) = dictionary_id {
+        header.extend_from_slice(&id.to_be_bytes());
+    }
+    header
+}
@@ -1,21 +1,40 @@
 mod util;
 
+use core::fmt::{self, Debug};
 use std::{
-    collections::{HashMap, hash_map},
+    collections::VecDeque,
     io::{self, Read, Write},
 };
 
 use scopeguard::guard;
 
 use crate::{
-    psb::{btree::util::SparseVec, table::StringTable},
+    psb::{
+        btree::util::{FreeSlots, SparseVec},
+        table::StringTable,
+    },
     value::{
         de,
-        util::{read_uint_array, write_uint_array},
+        util::{read_uint_array, uint_array_len, write_uint_array},
     },
 };
 
-pub fn read_btree(stream: &mut impl Read, buf: &mut Vec<u64>) -> Result<StringTable, de::Error> {
+/// Placement strategy of the double array encoding the PSB name table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum BtreePacking {
+    /// Places each block of children after every block placed before.
+    #[default]
+    Sequential,
+    /// Places each block of children at the first position where it fits, filling
+    /// holes left by earlier blocks. Produces a smaller name table, similar to the
+    /// official E-mote tools.
+    FirstFit,
+}
+
+pub fn read_btree(
+    stream: &mut impl Read,
+    buf: &mut Vec<u64>,
+) -> Result<(StringTable, NameTrie), de::Error> {
     let offsets_start = buf.len();
     let mut buf = guard(buf, |buf| {
         buf.drain(offsets_start..);
@@ -32,14 +51,14 @@ pub fn read_btree(stream: &mut impl Read, buf: &mut Vec<u64>) -> Result<StringTa
     let mut table = StringTable::with_capacity(buf.len() - indexes_start);
     let mut name = vec![];
     for &index in indexes {
-        let mut id = tree[index as usize];
+        let mut id = *tree.get(index as usize).ok_or(de::Error::InvalidValue)?;
 
         while id != 0 {
             // travel to child tree
-            let next = tree[id as usize];
+            let next = *tree.get(id as usize).ok_or(de::Error::InvalidValue)?;
 
             // get values from offsets
-            let decoded = id - offsets[next as usize];
+            let decoded = id - offsets.get(next as usize).ok_or(de::Error::InvalidValue)?;
 
             id = next;
 
@@ -50,156 +69,310 @@ pub fn read_btree(stream: &mut impl Read, buf: &mut Vec<u64>) -> Result<StringTa
         name.clear();
     }
 
-    Ok(table)
+    let trie = NameTrie {
+        offsets: to_u32_vec(offsets)?,
+        tree: to_u32_vec(tree)?,
+        indexes: to_u32_vec(indexes)?,
+    };
+    Ok((table, trie))
+}
+
+fn to_u32_vec(values: &[u64]) -> Result<Vec<u32>, de::Error> {
+    values
+        .iter()
+        .map(|&v| u32::try_from(v).map_err(|_| de::Error::InvalidValue))
+        .collect()
+}
+
+/// Decoded double array of a PSB name table, for looking up names without string
+/// comparisons.
+#[derive(Clone, Default)]
+pub struct NameTrie {
+    /// Base of the children of each node. Terminal nodes hold their name id.
+    offsets: Vec<u32>,
+    /// Parent of each node
+    tree: Vec<u32>,
+    /// Terminal node of each name
+    indexes: Vec<u32>,
+}
+
+impl NameTrie {
+    /// Returns the id of `name`.
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.terminal_index(self.node(name.as_bytes())?)
+    }
+
+    /// Appends the ids of names starting with `prefix` to `out`, in byte order.
+    pub fn find_prefixed(&self, prefix: &str, out: &mut Vec<usize>) {
+        let Some(node) = self.node(prefix.as_bytes()) else {
+            return;
+        };
+
+        let mut stack = vec![node];
+        while let Some(node) = stack.pop() {
+            if let Some(index) = self.terminal_index(node) {
+                out.push(index);
+            }
+
+            let stack_len = stack.len();
+            for value in 1..=u8::MAX {
+                if let Some(child) = self.child(node, value) {
+                    stack.push(child);
+                }
+            }
+            stack[stack_len..].reverse();
+        }
+    }
+
+    /// Walks from the root along `path`.
+    fn node(&self, path: &[u8]) -> Option<u32> {
+        path.iter()
+            .try_fold(0, |node, &value| self.child(node, value))
+    }
+
+    fn child(&self, node: u32, value: u8) -> Option<u32> {
+        let child = self.offsets.get(node as usize)?.checked_add(value as u32)?;
+        (child != node && child != 0 && *self.tree.get(child as usize)? == node).then_some(child)
+    }
+
+    fn terminal_index(&self, node: u32) -> Option<usize> {
+        let terminal = self.child(node, 0)?;
+
+        // Writers store the name id in the offset of terminal nodes, but it is not
+        // needed for decoding, so verify it.
+        let index = self.offsets[terminal as usize] as usize;
+        if self.indexes.get(index) == Some(&terminal) {
+            Some(index)
+        } else {
+            self.indexes.iter().position(|&id| id == terminal)
+        }
+    }
+}
+
+impl Debug for NameTrie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NameTrie")
+            .field("nodes", &self.tree.len())
+            .field("names", &self.indexes.len())
+            .finish()
+    }
 }
 
 pub struct PsbBtree(pub StringTable);
 
 impl PsbBtree {
-    pub fn write_tree(&self, stream: &mut impl Write) -> io::Result<()> {
+    pub fn write_tree(&self, packing: BtreePacking, stream: &mut impl Write) -> io::Result<()> {
+        for array in self.build_arrays(packing) {
+            write_uint_array(stream, &array)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the byte size of the tree written by [`write_tree`](PsbBtree::write_tree).
+    pub fn encoded_len(&self, packing: BtreePacking) -> u64 {
+        self.build_arrays(packing)
+            .iter()
+            .map(|array| uint_array_len(array.len(), array.iter().copied().max().unwrap_or(0)))
+            .sum()
+    }
+
+    /// Builds the offsets, tree and indexes arrays of the double array.
+    fn build_arrays(&self, packing: BtreePacking) -> [Vec<u64>; 3] {
         let mut root = self.build_tree();
 
         let mut offsets = SparseVec::new();
         let mut tree = SparseVec::new();
         let mut indexes = SparseVec::new();
 
-        offsets.push(1);
-        self.make_sub_tree(&mut root, Vec::new(), &mut offsets, &mut tree, &mut indexes);
+        match packing {
+            BtreePacking::Sequential => {
+                offsets.push(1);
+                make_sub_tree(&mut root, &mut offsets, &mut tree, &mut indexes);
+            }
+            BtreePacking::FirstFit => {
+                pack_first_fit(&mut root, &mut offsets, &mut tree, &mut indexes);
+            }
+        }
 
-        write_uint_array(stream, &offsets.into_inner())?;
-        write_uint_array(stream, &tree.into_inner())?;
-        write_uint_array(stream, &indexes.into_inner())?;
-        Ok(())
+        [
+            offsets.into_inner(),
+            tree.into_inner(),
+            indexes.into_inner(),
+        ]
     }
 
     fn build_tree(&self) -> TreeNode {
-        let mut root = TreeNode::new();
+        let mut root = TreeNode::new(0);
 
-        for data in self.0.iter() {
+        for (index, data) in self.0.iter().enumerate() {
             let mut last_node = &mut root;
 
             for byte in data.as_bytes() {
                 last_node = last_node.get_or_insert_mut(*byte);
             }
-            last_node.get_or_insert(0);
+            // Duplicated names resolve to the first one
+            last_node
+                .get_or_insert_mut(0)
+                .index
+                .get_or_insert(index as u64);
         }
 
         root
     }
+}
 
-    // Returns last node
-    fn make_sub_tree(
-        &self,
-        current_node: &mut TreeNode,
-        value: Vec<u8>,
-        offsets: &mut SparseVec<u64>,
-        tree: &mut SparseVec<u64>,
-        indexes: &mut SparseVec<u64>,
-    ) {
-        let min_value = *current_node.min_value().unwrap_or(&0);
-        let begin_pos = current_node.begin_pos;
-        let current_id = current_node.id;
-
-        // make_tree
-        for (child_value, child) in current_node.iter_mut() {
-            let id = if current_id == 0 || min_value < 1 {
-                *child_value as u64 + offsets.get(current_id as usize).unwrap()
-            } else {
-                (*child_value - min_value) as u64 + begin_pos
-            };
+fn make_sub_tree(
+    current_node: &mut TreeNode,
+    offsets: &mut SparseVec<u64>,
+    tree: &mut SparseVec<u64>,
+    indexes: &mut SparseVec<u64>,
+) {
+    let min_value = current_node.min_value().unwrap_or(0);
+    let begin_pos = current_node.begin_pos;
+    let current_id = current_node.id;
+
+    // make_tree
+    for child in &mut current_node.children {
+        let id = if current_id == 0 || min_value < 1 {
+            child.value as u64 + offsets.get(current_id as usize).unwrap()
+        } else {
+            (child.value - min_value) as u64 + begin_pos
+        };
+
+        tree.set(id as usize, current_id);
+        child.id = id;
+    }
 
-            tree.set(id as usize, current_id);
-            child.id = id;
-        }
+    for child in &mut current_node.children {
+        let child_max = child.max_value().unwrap_or(0) as usize;
+        let child_min = child.min_value().unwrap_or(0) as usize;
 
-        for (child_value, child) in current_node.iter_mut() {
-            let child_max = *child.max_value().unwrap_or(&0) as usize;
-            let child_min = *child.min_value().unwrap_or(&0) as usize;
+        let pos = {
+            let len = tree.len();
+            if len > child_max {
+                len
+            } else {
+                tree.set(child_max, 0);
 
-            let pos = {
-                let len = tree.len();
-                if len > child_max {
-                    len
-                } else {
-                    tree.set(child_max, 0);
+                tree.len()
+            }
+        };
 
-                    tree.len()
-                }
-            };
+        let count = child_max - child_min;
+        let end = pos + count;
 
-            let count = child_max - child_min;
-            let end = pos + count;
+        tree.set(end, 0);
 
-            tree.set(end, 0);
+        if let Some(index) = child.index {
+            offsets.set(child.id as usize, index);
+            indexes.set(index as usize, child.id);
+        } else {
+            let offset = (pos - child_min) as u64;
+            offsets.set(child.id as usize, offset);
+            child.begin_pos = pos as u64;
+        }
+    }
 
-            if *child_value == 0 {
-                let index = self
-                    .0
-                    .iter()
-                    .position(|val| val.as_bytes().eq(&value))
-                    .unwrap() as u64;
-                offsets.set(child.id as usize, index);
-                indexes.set(index as usize, child.id);
-            } else {
-                let offset = (pos - child_min) as u64;
-                offsets.set(child.id as usize, offset);
-                child.begin_pos = pos as u64;
-            }
+    for child in &mut current_node.children {
+        make_sub_tree(child, offsets, tree, indexes);
+    }
+}
+
+/// Places the children of every node, in breadth-first order, at the lowest base
+/// whose slots are all free.
+fn pack_first_fit(
+    root: &mut TreeNode,
+    offsets: &mut SparseVec<u64>,
+    tree: &mut SparseVec<u64>,
+    indexes: &mut SparseVec<u64>,
+) {
+    let mut slots = FreeSlots::new();
+    slots.take(0);
+
+    let mut queue = VecDeque::from([root]);
+    while let Some(node) = queue.pop_front() {
+        if let Some(index) = node.index {
+            offsets.set(node.id as usize, index);
+            indexes.set(index as usize, node.id);
+            continue;
         }
 
-        for (child_value, child) in current_node.iter_mut() {
-            let mut value = value.clone();
-            value.push(*child_value);
-            self.make_sub_tree(child, value, offsets, tree, indexes);
+        let Some(first) = node.min_value() else {
+            // empty table
+            offsets.set(node.id as usize, 1);
+            continue;
+        };
+        let first = first as usize;
+
+        let mut slot = slots.find(first);
+        let base = loop {
+            let base = slot - first;
+            if node
+                .children
+                .iter()
+                .all(|child| slots.is_free(base + child.value as usize))
+            {
+                break base;
+            }
+            slot = slots.find(slot + 1);
+        };
+
+        offsets.set(node.id as usize, base as u64);
+        for child in &mut node.children {
+            let id = base + child.value as usize;
+            slots.take(id);
+            tree.set(id, node.id);
+            child.id = id as u64;
+            queue.push_back(child);
         }
     }
 }
 
 #[derive(Debug)]
 struct TreeNode {
-    /// Children value, node
-    children: HashMap<u8, TreeNode>,
+    value: u8,
+    /// Children ordered by value, so the emitted layout is deterministic
+    children: Vec<TreeNode>,
+    /// Index of the name ending at this node, set on terminal nodes
+    index: Option<u64>,
 
     pub begin_pos: u64,
     pub id: u64,
 }
 
-impl Default for TreeNode {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
 impl TreeNode {
-    pub fn new() -> Self {
+    pub const fn new(value: u8) -> Self {
         Self {
-            children: HashMap::new(),
+            value,
+            children: vec![],
+            index: None,
             id: 0,
             begin_pos: 0,
         }
     }
 
-    pub fn min_value(&self) -> Option<&u8> {
-        self.children.keys().min()
-    }
-
-    pub fn max_value(&self) -> Option<&u8> {
-        self.children.keys().max()
+    pub fn min_value(&self) -> Option<u8> {
+        self.children.first().map(|child| child.value)
     }
 
-    pub fn iter_mut(&mut self) -> hash_map::IterMut<'_, u8, Self> {
-        self.children.iter_mut()
-    }
-
-    pub fn get_or_insert(&mut self, value: u8) -> &Self {
-        self.children.entry(value).or_default();
-
-        self.children.get(&value).unwrap()
+    pub fn max_value(&self) -> Option<u8> {
+        self.children.last().map(|child| child.value)
     }
 
     pub fn get_or_insert_mut(&mut self, value: u8) -> &mut Self {
-        self.children.entry(value).or_default();
-
-        self.children.get_mut(&value).unwrap()
+        // Names are usually sorted, so new children tend to go last
+        let index = match self.children.last() {
+            Some(last) if last.value < value => self.children.len(),
+            _ => match self
+                .children
+                .binary_search_by_key(&value, |child| child.value)
+            {
+                Ok(index) => return &mut self.children[index],
+                Err(index) => index,
+            },
+        };
+
+        self.children.insert(index, Self::new(value));
+        &mut self.children[index]
     }
 }
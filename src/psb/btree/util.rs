@@ -32,3 +32,42 @@ impl<T: Default + Clone> SparseVec<T> {
         self.vec
     }
 }
+
+/// Tracks occupied slots of a double array, finding the first free slot at or after
+/// an index in near-constant amortized time.
+#[derive(Debug)]
+pub struct FreeSlots {
+    /// Points to itself for free slots, and towards the next free slot otherwise
+    next: Vec<usize>,
+}
+
+impl FreeSlots {
+    pub const fn new() -> Self {
+        Self { next: Vec::new() }
+    }
+
+    pub fn is_free(&self, index: usize) -> bool {
+        self.next.get(index).is_none_or(|&next| next == index)
+    }
+
+    pub fn find(&mut self, mut index: usize) -> usize {
+        while let Some(&next) = self.next.get(index) {
+            if next == index {
+                break;
+            }
+            // path halving
+            let after = self.next.get(next).copied().unwrap_or(next);
+            self.next[index] = after;
+            index = after;
+        }
+        index
+    }
+
+    pub fn take(&mut self, index: usize) {
+        if self.next.len() <= index + 1 {
+            let len = self.next.len();
+            self.next.extend(len..index + 2);
+        }
+        self.next[index] = index + 1;
+    }
+}
@@ -2,6 +2,7 @@
 
 use std::io;
 
+use smol_str::SmolStr;
 use thiserror::Error;
 
 use crate::value::{de, ser};
@@ -37,6 +38,42 @@ pub enum PsbWriteError {
     #[error(transparent)]
     Serialize(#[from] ser::Error),
 
+    /// An object key is not in the name table of the file being rewritten.
+    #[error("name {0:?} is not in the name table")]
+    UnknownName(SmolStr),
+
+    /// A section would start beyond the 4 GiB addressable by the PSB header.
+    #[error("section offset {0} exceeds u32 limit")]
+    OffsetOverflow(u64),
+
+    /// More resources were added than resource values can index.
+    #[error("resource count {0} exceeds u32 limit")]
+    ResourceCountOverflow(usize),
+
+    /// An extra resource was added to a PSB version without the extra resource
+    /// section (version 3 or earlier).
+    #[error("psb version {0} does not support extra resources")]
+    ExtraResourcesUnsupported(u16),
+
+    /// The value tree references a resource index that was never added.
+    #[error("resource {0} is referenced but was never added")]
+    MissingResource(u64),
+
+    /// The value tree references an extra resource index that was never added.
+    #[error("extra resource {0} is referenced but was never added")]
+    MissingExtraResource(u64),
+
+    /// Added resources are never referenced by the value tree.
+    ///
+    /// See [`UnusedResources::Deny`](crate::psb::write::UnusedResources::Deny).
+    #[error("unused resources {resources:?} and extra resources {extra:?}")]
+    UnusedResources {
+        /// Indexes of the unused resources
+        resources: Vec<usize>,
+        /// Indexes of the unused extra resources
+        extra: Vec<usize>,
+    },
+
     /// An I/O error occurred while writing the stream.
     #[error(transparent)]
     Io(#[from] io::Error),
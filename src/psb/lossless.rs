@@ -0,0 +1,689 @@
+//! Byte-exact PSB round-trip support.
+//!
+//! [`PsbLossless`] keeps the original bytes of a PSB file together with the layout of
+//! every encoded value. Writing it back replays the original encoding for each subtree
+//! equal to the one read, so an unmodified file is reproduced byte for byte and a
+//! modified one only changes along the path to each modification.
+
+use std::{
+    collections::HashMap,
+    io::{Cursor, Write},
+};
+
+use adler2::Adler32;
+use byteorder::{LittleEndian, WriteBytesExt};
+use indexmap::IndexMap;
+use serde::Deserialize;
+use smol_str::SmolStr;
+
+use crate::{
+    psb::{error::PsbWriteError, table::StringTable},
+    value::{
+        PSB_COMPILER_ARRAY, PSB_COMPILER_BINARY_TREE, PSB_COMPILER_BOOL, PSB_COMPILER_DECIMAL,
+        PSB_COMPILER_INTEGER, PSB_COMPILER_RESOURCE, PSB_COMPILER_STRING, PSB_TYPE_DOUBLE,
+        PSB_TYPE_EXTRA_N, PSB_TYPE_FALSE, PSB_TYPE_FLOAT, PSB_TYPE_FLOAT0, PSB_TYPE_INTEGER_N,
+        PSB_TYPE_LIST, PSB_TYPE_NULL, PSB_TYPE_OBJECT, PSB_TYPE_RESOURCE_N, PSB_TYPE_STRING_N,
+        PSB_TYPE_TRUE, PsbValue,
+        de::{self, Deserializer},
+        number::PsbNumber,
+        ser,
+        util::{get_n, get_uint_n, read_uint_array, write_uint_array},
+    },
+};
+
+/// A PSB file read with its encoding, for byte-exact round-trips.
+///
+/// Obtained via [`PsbFile::read_lossless`]. Edit [`root`](PsbLossless::root) and
+/// write the result with [`write`](PsbLossless::write).
+///
+/// Subtrees equal to the original are copied from the source file, keeping integer
+/// widths, float and double choices, shared offsets and data order. Modified lists and
+/// objects are re-encoded with their children in the original order. Sections other
+/// than the value tree and string table, including resources, are copied unchanged.
+///
+/// [`PsbFile::read_lossless`]: crate::psb::read::PsbFile::read_lossless
+#[derive(Debug, Clone)]
+pub struct PsbLossless {
+    /// Root value, which may be modified before writing.
+    pub root: PsbValue,
+
+    original: PsbValue,
+    node: Node,
+    bytes: Vec<u8>,
+    version: u16,
+    names: StringTable,
+    strings: StringTable,
+    string_offsets: Vec<u64>,
+    /// End of the string offset array
+    string_offsets_end: usize,
+    sections: Vec<Section>,
+}
+
+impl PsbLossless {
+    /// Parses the PSB file in `bytes` with its decoded name and string tables.
+    pub(crate) fn parse(
+        bytes: Vec<u8>,
+        names: &StringTable,
+        strings: &StringTable,
+    ) -> Result<Self, de::Error> {
+        let header_u32 = |slot: usize| -> Result<u32, de::Error> {
+            let offset = 12 + slot * 4;
+            bytes
+                .get(offset..offset + 4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+                .ok_or(de::Error::InvalidValue)
+        };
+
+        let version = u16::from_le_bytes(
+            bytes
+                .get(4..6)
+                .ok_or(de::Error::InvalidValue)?
+                .try_into()
+                .unwrap(),
+        );
+
+        let mut sections = Vec::with_capacity(10);
+        for kind in SectionKind::ALL {
+            if kind.slot() >= header_slots(version) {
+                continue;
+            }
+            let start = header_u32(kind.slot())? as usize;
+            if start > bytes.len() {
+                return Err(de::Error::InvalidValue);
+            }
+            sections.push(Section { kind, start });
+        }
+        sections.sort_by_key(|section| (section.start, section.kind.tie_order()));
+
+        let mut stream = Cursor::new(&bytes[..]);
+        stream.set_position(header_u32(SectionKind::StringOffsets.slot())? as u64);
+        let mut string_offsets = vec![];
+        read_uint_array(&mut stream, &mut string_offsets)?;
+        let string_offsets_end = stream.position() as usize;
+
+        let entrypoint = header_u32(SectionKind::Tree.slot())? as usize;
+        let (node, original) = Node::parse(&bytes, entrypoint, names, strings)?;
+
+        Ok(Self {
+            root: original.clone(),
+            original,
+            node,
+            bytes,
+            version,
+            names: names.clone(),
+            strings: strings.clone(),
+            string_offsets,
+            string_offsets_end,
+            sections,
+        })
+    }
+
+    /// Returns `true` if [`root`](PsbLossless::root) differs from the value read.
+    pub fn is_modified(&self) -> bool {
+        !same_value(&self.root, &self.original)
+    }
+
+    /// Writes the PSB file to `stream`, replaying the original encoding.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`PsbWriteError::UnknownName`] if an object key is missing from the
+    /// original name table, as adding names renumbers every object in the file.
+    /// New strings are appended to the string table.
+    pub fn write(&self, stream: &mut impl Write) -> Result<(), PsbWriteError> {
+        let mut encoder = Encoder::new(self);
+        let mut tree = vec![];
+        encoder.encode(&self.node, &self.original, &self.root, &mut tree)?;
+
+        let header_length = header_length(self.version).min(self.bytes.len());
+        let mut header = self.bytes[..header_length].to_vec();
+        let mut body = vec![];
+        let first_start = self.sections.first().map_or(header_length, |s| s.start);
+        body.extend_from_slice(&self.bytes[header_length..first_start.max(header_length)]);
+
+        for (i, section) in self.sections.iter().enumerate() {
+            let end = self
+                .sections
+                .get(i + 1)
+                .map_or(self.bytes.len(), |next| next.start);
+            let span = &self.bytes[section.start..end];
+
+            let start = header_length + body.len();
+            let start =
+                u32::try_from(start).map_err(|_| PsbWriteError::OffsetOverflow(start as u64))?;
+            let slot = 12 + section.kind.slot() * 4;
+            header[slot..slot + 4].copy_from_slice(&start.to_le_bytes());
+
+            match section.kind {
+                SectionKind::Tree => {
+                    body.extend_from_slice(&tree);
+                    body.extend_from_slice(
+                        &self.bytes[self.node.end.clamp(section.start, end)..end],
+                    );
+                }
+
+                SectionKind::StringOffsets if !encoder.new_strings.is_empty() => {
+                    let mut offsets = self.string_offsets.clone();
+                    let mut offset = self.string_data_len() as u64;
+                    for string in &encoder.new_strings {
+                        offsets.push(offset);
+                        offset += string.len() as u64 + 1;
+                    }
+                    write_uint_array(&mut body, &offsets)?;
+                    body.extend_from_slice(
+                        &self.bytes[self.string_offsets_end.clamp(section.start, end)..end],
+                    );
+                }
+
+                SectionKind::StringData => {
+                    body.extend_from_slice(span);
+                    for string in &encoder.new_strings {
+                        body.extend_from_slice(string.as_bytes());
+                        body.push(0);
+                    }
+                }
+
+                _ => body.extend_from_slice(span),
+            }
+        }
+
+        if self.version > 2 && header[12..] != self.bytes[12..header_length] {
+            let mut adler = Adler32::new();
+            adler.write_slice(&header[8..40]);
+            if self.version > 3 {
+                adler.write_slice(&header[44..56]);
+            }
+            header[40..44].copy_from_slice(&adler.checksum().to_le_bytes());
+        }
+
+        stream.write_all(&header)?;
+        stream.write_all(&body)?;
+        Ok(())
+    }
+
+    /// Length of the original string data section.
+    fn string_data_len(&self) -> usize {
+        let index = self
+            .sections
+            .iter()
+            .position(|section| section.kind == SectionKind::StringData)
+            .unwrap();
+        let end = self
+            .sections
+            .get(index + 1)
+            .map_or(self.bytes.len(), |next| next.start);
+        end - self.sections[index].start
+    }
+}
+
+/// Section of a PSB file located by a header offset.
+#[derive(Debug, Clone, Copy)]
+struct Section {
+    kind: SectionKind,
+    start: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SectionKind {
+    Names,
+    StringOffsets,
+    StringData,
+    ResourceOffsets,
+    ResourceLengths,
+    ResourceData,
+    Tree,
+    ExtraOffsets,
+    ExtraLengths,
+    ExtraData,
+}
+
+impl SectionKind {
+    const ALL: [Self; 10] = [
+        Self::Names,
+        Self::StringOffsets,
+        Self::StringData,
+        Self::ResourceOffsets,
+        Self::ResourceLengths,
+        Self::ResourceData,
+        Self::Tree,
+        Self::ExtraOffsets,
+        Self::ExtraLengths,
+        Self::ExtraData,
+    ];
+
+    /// Index of the u32 header field holding the section offset, after the
+    /// signature, version, flags and header length.
+    const fn slot(self) -> usize {
+        match self {
+            Self::Names => 0,
+            Self::StringOffsets => 1,
+            Self::StringData => 2,
+            Self::ResourceOffsets => 3,
+            Self::ResourceLengths => 4,
+            Self::ResourceData => 5,
+            Self::Tree => 6,
+            // slot 7 is the checksum
+            Self::ExtraOffsets => 8,
+            Self::ExtraLengths => 9,
+            Self::ExtraData => 10,
+        }
+    }
+
+    /// Order of sections starting at the same offset. Data sections may be empty and
+    /// go first so the other section keeps the bytes.
+    const fn tie_order(self) -> u8 {
+        match self {
+            Self::StringData | Self::ResourceData | Self::ExtraData => 0,
+            _ => 1,
+        }
+    }
+}
+
+const fn header_slots(version: u16) -> usize {
+    match version {
+        ..3 => 7,
+        3 => 8,
+        _ => 11,
+    }
+}
+
+const fn header_length(version: u16) -> usize {
+    12 + header_slots(version) * 4
+}
+
+/// Encoded extent of a value in the source file.
+#[derive(Debug, Clone)]
+struct Node {
+    start: usize,
+    /// End of the value including its children and any gaps between them
+    end: usize,
+    kind: NodeKind,
+}
+
+#[derive(Debug, Clone)]
+enum NodeKind {
+    Leaf,
+    List {
+        offsets: Vec<u64>,
+        children: Vec<Node>,
+    },
+    Object {
+        names: Vec<u64>,
+        offsets: Vec<u64>,
+        children: Vec<Node>,
+    },
+}
+
+impl Node {
+    fn parse(
+        bytes: &[u8],
+        start: usize,
+        names: &StringTable,
+        strings: &StringTable,
+    ) -> Result<(Self, PsbValue), de::Error> {
+        let mut stream = Cursor::new(bytes);
+        stream.set_position(start as u64);
+
+        match bytes.get(start).copied() {
+            Some(PSB_TYPE_LIST) => {
+                stream.set_position(start as u64 + 1);
+                let mut offsets = vec![];
+                read_uint_array(&mut stream, &mut offsets)?;
+                let data_start = stream.position() as usize;
+
+                let mut end = data_start;
+                let mut children = Vec::with_capacity(offsets.len());
+                let mut values = Vec::with_capacity(offsets.len());
+                for &offset in &offsets {
+                    let (child, value) =
+                        Self::parse(bytes, data_start + offset as usize, names, strings)?;
+                    end = end.max(child.end);
+                    children.push(child);
+                    values.push(value);
+                }
+
+                Ok((
+                    Self {
+                        start,
+                        end,
+                        kind: NodeKind::List { offsets, children },
+                    },
+                    PsbValue::List(values),
+                ))
+            }
+
+            Some(PSB_TYPE_OBJECT) => {
+                stream.set_position(start as u64 + 1);
+                let mut ids = vec![];
+                read_uint_array(&mut stream, &mut ids)?;
+                let mut offsets = vec![];
+                read_uint_array(&mut stream, &mut offsets)?;
+                if ids.len() != offsets.len() {
+                    return Err(de::Error::InvalidValue);
+                }
+                let data_start = stream.position() as usize;
+
+                let mut end = data_start;
+                let mut children = Vec::with_capacity(offsets.len());
+                let mut map = IndexMap::with_capacity(offsets.len());
+                for (&id, &offset) in ids.iter().zip(&offsets) {
+                    let name = names.get(id as _).ok_or(de::Error::InvalidValue)?;
+                    let (child, value) =
+                        Self::parse(bytes, data_start + offset as usize, names, strings)?;
+                    end = end.max(child.end);
+                    children.push(child);
+                    map.insert(SmolStr::new(name), value);
+                }
+
+                Ok((
+                    Self {
+                        start,
+                        end,
+                        kind: NodeKind::Object {
+                            names: ids,
+                            offsets,
+                            children,
+                        },
+                    },
+                    PsbValue::Object(map),
+                ))
+            }
+
+            _ => {
+                let mut de = Deserializer::new(names, strings, &mut stream);
+                let value = PsbValue::deserialize(&mut de)?;
+                Ok((
+                    Self {
+                        start,
+                        end: stream.position() as usize,
+                        kind: NodeKind::Leaf,
+                    },
+                    value,
+                ))
+            }
+        }
+    }
+}
+
+struct Encoder<'a> {
+    src: &'a PsbLossless,
+    names: HashMap<&'a str, u64>,
+    strings: HashMap<SmolStr, u64>,
+    /// Strings appended to the string table
+    new_strings: Vec<SmolStr>,
+}
+
+impl<'a> Encoder<'a> {
+    fn new(src: &'a PsbLossless) -> Self {
+        let mut names = HashMap::new();
+        for (id, name) in src.names.iter().enumerate() {
+            names.entry(name).or_insert(id as u64);
+        }
+
+        let mut strings = HashMap::new();
+        for (id, string) in src.strings.iter().enumerate() {
+            strings.entry(SmolStr::new(string)).or_insert(id as u64);
+        }
+
+        Self {
+            src,
+            names,
+            strings,
+            new_strings: vec![],
+        }
+    }
+
+    /// Encodes `value` whose original encoding is `node` of the `original` value.
+    fn encode(
+        &mut self,
+        node: &Node,
+        original: &PsbValue,
+        value: &PsbValue,
+        out: &mut Vec<u8>,
+    ) -> Result<(), PsbWriteError> {
+        if same_value(original, value) {
+            out.extend_from_slice(&self.src.bytes[node.start..node.end]);
+            return Ok(());
+        }
+
+        match (&node.kind, original, value) {
+            (
+                NodeKind::List { offsets, children },
+                PsbValue::List(original),
+                PsbValue::List(list),
+            ) => {
+                let mut encoded = Vec::with_capacity(list.len());
+                for (i, value) in list.iter().enumerate() {
+                    let mut child = vec![];
+                    match (children.get(i), original.get(i)) {
+                        (Some(node), Some(original)) => {
+                            self.encode(node, original, value, &mut child)?
+                        }
+                        _ => self.encode_new(value, &mut child)?,
+                    }
+                    encoded.push((offsets.get(i).copied(), child));
+                }
+
+                out.push(PSB_TYPE_LIST);
+                write_children(out, encoded)?;
+                Ok(())
+            }
+
+            (
+                NodeKind::Object {
+                    names,
+                    offsets,
+                    children,
+                },
+                PsbValue::Object(original),
+                PsbValue::Object(map),
+            ) => {
+                let mut entries = map
+                    .iter()
+                    .map(|(key, value)| Ok((self.name_id(key)?, value)))
+                    .collect::<Result<Vec<_>, PsbWriteError>>()?;
+                entries.sort_unstable_by_key(|&(id, _)| id);
+
+                let indices = names
+                    .iter()
+                    .enumerate()
+                    .map(|(index, &id)| (id, index))
+                    .collect::<HashMap<_, _>>();
+                let mut ids = Vec::with_capacity(entries.len());
+                let mut encoded = Vec::with_capacity(entries.len());
+                for (id, value) in entries {
+                    let mut child = vec![];
+                    let index = indices.get(&id).copied();
+                    let name = self.src.names.get(id as _).unwrap();
+                    let original_offset = match (index, original.get(name)) {
+                        (Some(index), Some(original)) => {
+                            self.encode(&children[index], original, value, &mut child)?;
+                            Some(offsets[index])
+                        }
+                        _ => {
+                            self.encode_new(value, &mut child)?;
+                            None
+                        }
+                    };
+                    ids.push(id);
+                    encoded.push((original_offset, child));
+                }
+
+                out.push(PSB_TYPE_OBJECT);
+                write_uint_array(out, &ids)?;
+                write_children(out, encoded)?;
+                Ok(())
+            }
+
+            _ => self.encode_new(value, out),
+        }
+    }
+
+    /// Encodes a value without an original encoding.
+    fn encode_new(&mut self, value: &PsbValue, out: &mut Vec<u8>) -> Result<(), PsbWriteError> {
+        match value {
+            PsbValue::Null => out.push(PSB_TYPE_NULL),
+            PsbValue::Bool(false) => out.push(PSB_TYPE_FALSE),
+            PsbValue::Bool(true) => out.push(PSB_TYPE_TRUE),
+
+            PsbValue::Number(PsbNumber::Integer(0)) => out.push(PSB_TYPE_INTEGER_N),
+            &PsbValue::Number(PsbNumber::Integer(v)) => {
+                let n = get_n(v);
+                out.push(PSB_TYPE_INTEGER_N + n);
+                out.extend_from_slice(&v.to_le_bytes()[..n as usize]);
+            }
+            PsbValue::Number(PsbNumber::Float(v)) if *v == 0.0 => out.push(PSB_TYPE_FLOAT0),
+            &PsbValue::Number(PsbNumber::Float(v)) => {
+                out.push(PSB_TYPE_FLOAT);
+                out.write_f32::<LittleEndian>(v)?;
+            }
+            &PsbValue::Number(PsbNumber::Double(v)) => {
+                out.push(PSB_TYPE_DOUBLE);
+                out.write_f64::<LittleEndian>(v)?;
+            }
+
+            PsbValue::String(string) => {
+                let id = self.string_id(string);
+                write_index(out, PSB_TYPE_STRING_N, id)?;
+            }
+            &PsbValue::Resource(index) => write_index(out, PSB_TYPE_RESOURCE_N, index as _)?,
+            &PsbValue::ExtraResource(index) => write_index(out, PSB_TYPE_EXTRA_N, index as _)?,
+
+            PsbValue::List(list) => {
+                let mut encoded = Vec::with_capacity(list.len());
+                for value in list {
+                    let mut child = vec![];
+                    self.encode_new(value, &mut child)?;
+                    encoded.push((None, child));
+                }
+
+                out.push(PSB_TYPE_LIST);
+                write_children(out, encoded)?;
+            }
+
+            PsbValue::IntArray(items) => write_uint_array(out, items)?,
+
+            PsbValue::Object(map) => {
+                let mut entries = map
+                    .iter()
+                    .map(|(key, value)| Ok((self.name_id(key)?, value)))
+                    .collect::<Result<Vec<_>, PsbWriteError>>()?;
+                entries.sort_unstable_by_key(|&(id, _)| id);
+
+                let mut ids = Vec::with_capacity(entries.len());
+                let mut encoded = Vec::with_capacity(entries.len());
+                for (id, value) in entries {
+                    let mut child = vec![];
+                    self.encode_new(value, &mut child)?;
+                    ids.push(id);
+                    encoded.push((None, child));
+                }
+
+                out.push(PSB_TYPE_OBJECT);
+                write_uint_array(out, &ids)?;
+                write_children(out, encoded)?;
+            }
+
+            PsbValue::CompilerNumber => out.push(PSB_COMPILER_INTEGER),
+            PsbValue::CompilerString => out.push(PSB_COMPILER_STRING),
+            PsbValue::CompilerResource => out.push(PSB_COMPILER_RESOURCE),
+            PsbValue::CompilerDecimal => out.push(PSB_COMPILER_DECIMAL),
+            PsbValue::CompilerArray => out.push(PSB_COMPILER_ARRAY),
+            PsbValue::CompilerBool => out.push(PSB_COMPILER_BOOL),
+            PsbValue::CompilerBinaryTree => out.push(PSB_COMPILER_BINARY_TREE),
+        }
+
+        Ok(())
+    }
+
+    fn name_id(&self, name: &SmolStr) -> Result<u64, PsbWriteError> {
+        self.names
+            .get(name.as_str())
+            .copied()
+            .ok_or_else(|| PsbWriteError::UnknownName(name.clone()))
+    }
+
+    fn string_id(&mut self, string: &SmolStr) -> u64 {
+        if let Some(&id) = self.strings.get(string) {
+            return id;
+        }
+
+        let id = (self.src.strings.len() + self.new_strings.len()) as u64;
+        self.strings.insert(string.clone(), id);
+        self.new_strings.push(string.clone());
+        id
+    }
+}
+
+/// Returns `true` if `a` and `b` are the same value, comparing numbers by their bits
+/// so that NaN equals itself.
+fn same_value(a: &PsbValue, b: &PsbValue) -> bool {
+    match (a, b) {
+        (PsbValue::Number(a), PsbValue::Number(b)) => match (a, b) {
+            (PsbNumber::Integer(a), PsbNumber::Integer(b)) => a == b,
+            (PsbNumber::Double(a), PsbNumber::Double(b)) => a.to_bits() == b.to_bits(),
+            (PsbNumber::Float(a), PsbNumber::Float(b)) => a.to_bits() == b.to_bits(),
+            _ => false,
+        },
+        (PsbValue::List(a), PsbValue::List(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same_value(a, b))
+        }
+        (PsbValue::Object(a), PsbValue::Object(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).is_some_and(|b| same_value(a, b)))
+        }
+        _ => a == b,
+    }
+}
+
+/// Writes the offset array and data of children given with their original offset,
+/// keeping the original data order and sharing.
+fn write_children(
+    out: &mut Vec<u8>,
+    children: Vec<(Option<u64>, Vec<u8>)>,
+) -> Result<(), PsbWriteError> {
+    let mut order = (0..children.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| (children[i].0.is_none(), children[i].0, i));
+
+    let mut offsets = vec![0_u64; children.len()];
+    let mut data = Vec::<u8>::new();
+    // New offset and index of written children by original offset
+    let mut written = HashMap::<u64, Vec<(u64, usize)>>::new();
+    for i in order {
+        let (original_offset, ref bytes) = children[i];
+        let shared = original_offset
+            .and_then(|offset| written.get(&offset))
+            .and_then(|list| list.iter().find(|&&(_, j)| children[j].1 == *bytes));
+
+        offsets[i] = match shared {
+            Some(&(offset, _)) => offset,
+            None => {
+                let offset = data.len() as u64;
+                data.extend_from_slice(bytes);
+                if let Some(original_offset) = original_offset {
+                    written
+                        .entry(original_offset)
+                        .or_default()
+                        .push((offset, i));
+                }
+                offset
+            }
+        };
+    }
+
+    write_uint_array(out, &offsets)?;
+    out.extend_from_slice(&data);
+    Ok(())
+}
+
+fn write_index(out: &mut Vec<u8>, base: u8, index: u64) -> Result<(), PsbWriteError> {
+    let n = get_uint_n(index);
+    if n > 4 {
+        return Err(ser::Error::IndexOverflow.into());
+    }
+
+    out.push(base + n);
+    out.extend_from_slice(&index.to_le_bytes()[..n as usize]);
+    Ok(())
+}
@@ -1,6 +1,7 @@
 //! PSB/MDF reading and writing support.
 
 pub mod error;
+pub mod lossless;
 pub mod read;
 pub mod table;
 pub mod write;
@@ -8,7 +8,12 @@ use serde::de::DeserializeOwned;
 
 use crate::{
     PSB_SIGNATURE,
-    psb::{btree::read_btree, error::PsbOpenError, table::StringTable},
+    psb::{
+        btree::{NameTrie, read_btree},
+        error::PsbOpenError,
+        lossless::PsbLossless,
+        table::StringTable,
+    },
     value::{
         de::{self, Deserializer},
         util::read_uint_array,
@@ -27,6 +32,7 @@ pub struct PsbFile<T> {
 
     /// Table of property names used in PSB objects.
     pub names: StringTable,
+    trie: NameTrie,
     /// Table of string values referenced by PSB string entries.
     pub strings: StringTable,
     resources: Vec<PsbResourceItem>,
@@ -37,6 +43,8 @@ pub struct PsbFile<T> {
     /// Adler-32 checksum of the PSB header offsets, present in version 3 and later.
     pub checksum: Option<u32>,
     extra: Vec<PsbResourceItem>,
+    /// Offset of the PSB signature in `stream`
+    start: u64,
     stream: T,
 }
 
@@ -93,7 +101,7 @@ impl<T: BufRead + Seek> PsbFile<T> {
         };
 
         stream.seek(std::io::SeekFrom::Start(start + name_offset as u64))?;
-        let names = read_btree(&mut stream, &mut buf).map_err(PsbOpenError::Names)?;
+        let (names, trie) = read_btree(&mut stream, &mut buf).map_err(PsbOpenError::Names)?;
 
         stream.seek(SeekFrom::Start(start + string_offset as u64))?;
         let strings = Self::read_strings(&mut stream, &mut buf, start + string_data_start as u64)
@@ -112,11 +120,13 @@ impl<T: BufRead + Seek> PsbFile<T> {
             encrypted,
             version,
             names,
+            trie,
             strings,
             resources,
             entrypoint: start + entrypoint as u64,
             checksum,
             extra,
+            start,
             stream,
         })
     }
@@ -181,16 +191,35 @@ impl<T: BufRead + Seek> PsbFile<T> {
         self.extra.len()
     }
 
+    /// Returns the id of `name` in the name table.
+    ///
+    /// The name is looked up in the name trie of the file, without string
+    /// comparisons. Name ids of an object are sorted, so the id can then be located
+    /// in an object with a binary search.
+    pub fn name_id(&self, name: &str) -> Option<usize> {
+        self.trie.find(name)
+    }
+
+    /// Returns the names starting with `prefix` with their ids, in byte order.
+    pub fn names_with_prefix(&self, prefix: &str) -> impl Iterator<Item = (usize, &str)> {
+        let mut ids = vec![];
+        self.trie.find_prefixed(prefix, &mut ids);
+        ids.into_iter()
+            .filter_map(|id| Some((id, self.names.get(id)?)))
+    }
+
     /// Returns a [`Deserializer`] positioned at the root value of the PSB file.
     ///
     /// The deserializer borrows the file's name/string tables and the underlying stream.
+    /// [`PsbBytes`] values are loaded from the resources of the file.
+    ///
+    /// [`PsbBytes`]: crate::value::PsbBytes
     pub fn root_deserializer<'a>(&'a mut self) -> io::Result<Deserializer<'a, &'a mut T>> {
         self.stream.seek(SeekFrom::Start(self.entrypoint))?;
-        Ok(Deserializer::new(
-            &self.names,
-            &self.strings,
-            &mut self.stream,
-        ))
+        Ok(
+            Deserializer::new(&self.names, &self.strings, &mut self.stream)
+                .with_resources(&self.resources, &self.extra),
+        )
     }
 
     /// Deserializes the root PSB value into the requested type `V`.
@@ -202,6 +231,39 @@ impl<T: BufRead + Seek> PsbFile<T> {
         V::deserialize(&mut self.root_deserializer()?)
     }
 
+    /// Deserializes the root PSB value into the requested type `V`, reading resource
+    /// and extra resource references as their data.
+    ///
+    /// Fields typed as byte sequences (e.g. `Vec<u8>`) or [`PsbBytes`] receive the
+    /// resource data, so typed models are populated without opening each resource.
+    /// See [`Deserializer::resolve_resources`].
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`de::Error`] if the root value cannot be deserialized as `V`, or a
+    /// referenced resource is missing.
+    ///
+    /// [`PsbBytes`]: crate::value::PsbBytes
+    pub fn deserialize_root_with_resources<V: DeserializeOwned>(&mut self) -> Result<V, de::Error> {
+        V::deserialize(&mut self.root_deserializer()?.resolve_resources(true))
+    }
+
+    /// Reads the whole file with the encoding of every value, for byte-exact
+    /// round-trips.
+    ///
+    /// Everything from the PSB signature to the end of the stream is loaded into memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`de::Error`] if the file cannot be read or the root value cannot be
+    /// decoded.
+    pub fn read_lossless(&mut self) -> Result<PsbLossless, de::Error> {
+        self.stream.seek(SeekFrom::Start(self.start))?;
+        let mut bytes = vec![];
+        self.stream.read_to_end(&mut bytes)?;
+        PsbLossless::parse(bytes, &self.names, &self.strings)
+    }
+
     /// Opens a stream over the binary resource at the given `index`.
     ///
     /// Returns `Ok(None)` if `index` is out of range.
@@ -282,7 +344,7 @@ impl<T: BufRead> BufRead for PsbResourceStream<'_, T> {
 }
 
 #[derive(Debug, Clone, Copy)]
-struct PsbResourceItem {
-    position: u64,
-    size: u64,
+pub(crate) struct PsbResourceItem {
+    pub position: u64,
+    pub size: u64,
 }
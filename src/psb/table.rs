@@ -1,6 +1,11 @@
 //! PSB string table used to store names and string values.
 
 use core::fmt::Debug;
+use std::collections::HashMap;
+
+use indexmap::IndexSet;
+use smol_str::SmolStr;
+use twox_hash::XxHash3_64;
 
 /// A compact, append-only table of strings backed by a single contiguous buffer.
 ///
@@ -10,10 +15,15 @@ use core::fmt::Debug;
 ///
 /// This structure is used internally to hold the PSB name table (object keys) and
 /// the string value table.
+///
+/// Lookups by string scan the table, unless a hash index is built with
+/// [`StringTable::build_index`].
 #[derive(Clone)]
 pub struct StringTable {
     data: String,
     indices: Vec<usize>,
+    /// Hash of each string to the first entry holding it
+    index: Option<HashMap<u64, usize>>,
 }
 
 impl Default for StringTable {
@@ -28,6 +38,7 @@ impl StringTable {
         Self {
             data: String::new(),
             indices: vec![],
+            index: None,
         }
     }
 
@@ -36,6 +47,7 @@ impl StringTable {
         Self {
             data: String::new(),
             indices: Vec::with_capacity(size),
+            index: None,
         }
     }
 
@@ -43,43 +55,106 @@ impl StringTable {
     pub fn push(&mut self, data: impl IntoIterator<Item = char>) -> usize {
         let start = self.data.len();
         self.data.extend(data);
-        let id = self.indices.len();
-        self.indices.push(start);
-        id
+        self.push_entry(start)
     }
 
     /// Appends `data` to the table and returns its identifier.
     pub fn push_str(&mut self, data: &str) -> usize {
         let start = self.data.len();
         self.data.push_str(data);
+        self.push_entry(start)
+    }
+
+    fn push_entry(&mut self, start: usize) -> usize {
         let id = self.indices.len();
         self.indices.push(start);
+        if let Some(index) = &mut self.index {
+            index
+                .entry(XxHash3_64::oneshot(&self.data.as_bytes()[start..]))
+                .or_insert(id);
+        }
         id
     }
 
     /// Returns the string with the given `id`, or `None` if `id` is out of range.
     pub fn get(&self, id: usize) -> Option<&str> {
         let start = *self.indices.get(id)?;
-        let end = self.indices.get(id + 1).copied();
-        Some(if let Some(end) = end {
-            &self.data[start..end]
-        } else {
-            &self.data[start..]
-        })
+        Some(&self.data[start..self.end(id)])
     }
 
     /// Returns `true` if the table contains no strings.
     #[inline]
     pub const fn is_empty(&self) -> bool {
-        self.data.is_empty()
+        self.indices.is_empty()
     }
 
-    /// Returns the total byte length of all stored strings (not the number of entries).
+    /// Returns the number of strings in the table.
     #[inline]
     pub const fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    /// Returns the total byte length of all stored strings.
+    #[inline]
+    pub const fn byte_len(&self) -> usize {
         self.data.len()
     }
 
+    /// Builds a hash index of the table, so [`StringTable::position`] no longer
+    /// scans the table. The index is kept up to date by later pushes.
+    pub fn build_index(&mut self) {
+        if self.index.is_some() {
+            return;
+        }
+
+        let mut index = HashMap::with_capacity(self.len());
+        for (id, data) in self.iter().enumerate() {
+            index
+                .entry(XxHash3_64::oneshot(data.as_bytes()))
+                .or_insert(id);
+        }
+        self.index = Some(index);
+    }
+
+    /// Returns the identifier of the first entry equal to `data`.
+    pub fn position(&self, data: &str) -> Option<usize> {
+        if let Some(index) = &self.index {
+            let id = *index.get(&XxHash3_64::oneshot(data.as_bytes()))?;
+            if self.get(id) == Some(data) {
+                return Some(id);
+            }
+        }
+
+        self.iter().position(|entry| entry == data)
+    }
+
+    /// Binary searches the table for `data`, like [`slice::binary_search`].
+    ///
+    /// The table must be sorted, as name tables written by the serializer are.
+    pub fn binary_search(&self, data: &str) -> Result<usize, usize> {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.get(mid).unwrap().cmp(data) {
+                core::cmp::Ordering::Less => low = mid + 1,
+                core::cmp::Ordering::Greater => high = mid,
+                core::cmp::Ordering::Equal => return Ok(mid),
+            }
+        }
+        Err(low)
+    }
+
+    /// Returns the entries containing `pattern` with their identifiers.
+    pub fn search<'a>(&'a self, pattern: &'a str) -> impl Iterator<Item = (usize, &'a str)> {
+        self.iter()
+            .enumerate()
+            .filter(move |(_, entry)| entry.contains(pattern))
+    }
+
+    fn end(&self, id: usize) -> usize {
+        self.indices.get(id + 1).copied().unwrap_or(self.data.len())
+    }
+
     /// Returns an iterator over all strings in insertion order.
     pub fn iter(&self) -> impl Iterator<Item = &str> {
         (0..self.indices.len()).flat_map(|i| self.get(i))
@@ -91,3 +166,39 @@ impl Debug for StringTable {
         f.debug_list().entries(self.iter()).finish()
     }
 }
+
+impl<S: AsRef<str>> FromIterator<S> for StringTable {
+    fn from_iter<I: IntoIterator<Item = S>>(iter: I) -> Self {
+        let mut table = Self::new();
+        for data in iter {
+            table.push_str(data.as_ref());
+        }
+        table
+    }
+}
+
+impl From<&IndexSet<SmolStr>> for StringTable {
+    fn from(set: &IndexSet<SmolStr>) -> Self {
+        set.iter().collect()
+    }
+}
+
+impl From<IndexSet<SmolStr>> for StringTable {
+    fn from(set: IndexSet<SmolStr>) -> Self {
+        Self::from(&set)
+    }
+}
+
+/// Duplicate entries are merged into the first one.
+impl From<&StringTable> for IndexSet<SmolStr> {
+    fn from(table: &StringTable) -> Self {
+        table.iter().map(SmolStr::new).collect()
+    }
+}
+
+/// Duplicate entries are merged into the first one.
+impl From<StringTable> for IndexSet<SmolStr> {
+    fn from(table: StringTable) -> Self {
+        Self::from(&table)
+    }
+}
@@ -1,46 +1,318 @@
 //! PSB file writing support.
 
-use core::fmt::{self, Debug};
-use std::io::{self, Read, Seek, SeekFrom, Write};
+pub use crate::psb::btree::BtreePacking;
+
+use core::{
+    cmp::Ordering,
+    fmt::{self, Debug},
+    hash::Hasher,
+    mem,
+};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io::{self, Read, Seek, SeekFrom, Write},
+};
 
 use adler2::Adler32;
 use byteorder::{LittleEndian, WriteBytesExt};
 use serde::Serialize;
 use smol_str::SmolStr;
+use twox_hash::XxHash3_64;
 
 use crate::{
     PSB_SIGNATURE,
     psb::{btree::PsbBtree, error::PsbWriteError, table::StringTable},
     value::{
-        ser::{Buffer, serialize},
-        util::write_uint_array,
+        ser::{Buffer, EncodedTree, ResourceRef, SpooledTree, serialize},
+        util::{uint_array_len, write_uint_array},
     },
 };
 
+/// Options for writing a PSB file with [`PsbWriter::with_options`].
+///
+/// ```
+/// use emote_psb::psb::write::PsbWriterOptions;
+///
+/// let options = PsbWriterOptions::new(3).encrypted(false).dedup(true);
+/// ```
+#[derive(Debug, Clone)]
+pub struct PsbWriterOptions {
+    version: u16,
+    encrypted: bool,
+    dedup: bool,
+    resource_hashing: bool,
+    resource_dedup: ResourceDedup,
+    unused_resources: UnusedResources,
+    resource_alignment: u64,
+    extra_placement: ExtraPlacement,
+    btree_packing: BtreePacking,
+}
+
+impl PsbWriterOptions {
+    /// Creates options for an unencrypted PSB file of the given format `version`
+    /// (2, 3, or 4).
+    pub const fn new(version: u16) -> Self {
+        Self {
+            version,
+            encrypted: false,
+            dedup: false,
+            resource_hashing: false,
+            resource_dedup: ResourceDedup::Disabled,
+            unused_resources: UnusedResources::Keep,
+            resource_alignment: 1,
+            extra_placement: ExtraPlacement::BeforeResources,
+            btree_packing: BtreePacking::Sequential,
+        }
+    }
+
+    /// Sets whether the PSB encryption flag should be set.
+    pub const fn encrypted(mut self, encrypted: bool) -> Self {
+        self.encrypted = encrypted;
+        self
+    }
+
+    /// Sets whether identical subtrees within a list or object share the same
+    /// encoded bytes, as the official E-mote compiler does.
+    ///
+    /// See [`Buffer::set_dedup`].
+    pub const fn dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Sets whether the content hash of each added resource is computed.
+    ///
+    /// The hash is the 64-bit XXH3 of the resource contents, available through
+    /// [`PsbWriter::resource_hash`] and [`PsbWriter::extra_resource_hash`].
+    /// Computing it reads each resource stream one extra time.
+    pub const fn resource_hashing(mut self, resource_hashing: bool) -> Self {
+        self.resource_hashing = resource_hashing;
+        self
+    }
+
+    /// Sets how resources with identical contents are handled.
+    ///
+    /// Deduplication implies [`resource_hashing`](PsbWriterOptions::resource_hashing).
+    /// Resources and extra resources are deduplicated separately.
+    pub const fn resource_dedup(mut self, resource_dedup: ResourceDedup) -> Self {
+        self.resource_dedup = resource_dedup;
+        self
+    }
+
+    /// Sets how resources not referenced by the value tree are handled.
+    pub const fn unused_resources(mut self, unused_resources: UnusedResources) -> Self {
+        self.unused_resources = unused_resources;
+        self
+    }
+
+    /// Sets the alignment of the data of each resource from the start of the file,
+    /// in bytes. Resources are padded with zeros to the next aligned offset.
+    ///
+    /// Defaults to 1, packing resources back to back. An alignment of 0 is treated
+    /// as 1.
+    pub const fn resource_alignment(mut self, resource_alignment: u64) -> Self {
+        self.resource_alignment = resource_alignment;
+        self
+    }
+
+    /// Sets whether the extra resource sections of version 4 files are written
+    /// before or after the resource sections.
+    pub const fn extra_placement(mut self, extra_placement: ExtraPlacement) -> Self {
+        self.extra_placement = extra_placement;
+        self
+    }
+
+    /// Sets how the name table is packed into its double array.
+    pub const fn btree_packing(mut self, btree_packing: BtreePacking) -> Self {
+        self.btree_packing = btree_packing;
+        self
+    }
+}
+
+/// Handling of added resources that the value tree never references.
+///
+/// Except for [`UnusedResources::Keep`], the resource references of the value tree
+/// are collected when the [`PsbWriter`] is created and checked in
+/// [`PsbWriter::finish`]. Resources and extra resources are checked separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum UnusedResources {
+    /// Writes every added resource.
+    #[default]
+    Keep,
+    /// Fails with [`PsbWriteError::UnusedResources`] if any resource is unused.
+    Deny,
+    /// Removes unused resources, renumbering the references of the value tree to
+    /// the remaining ones. Renumbered references keep their encoded width.
+    Remove,
+}
+
+/// Placement of the extra resource sections relative to the resource sections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ExtraPlacement {
+    /// Writes extra resources before resources.
+    #[default]
+    BeforeResources,
+    /// Writes extra resources after resources.
+    AfterResources,
+}
+
+/// Description of an added resource, given to the ordering callback of
+/// [`PsbWriter::order_resources_by`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ResourceInfo {
+    /// Index of the resource
+    pub index: usize,
+    /// Length of the resource in bytes
+    pub len: u64,
+    /// Content hash of the resource, see [`PsbWriter::resource_hash`]
+    pub hash: Option<u64>,
+}
+
+/// Byte sizes of the sections of a PSB file, computed by [`estimate_size`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[non_exhaustive]
+pub struct PsbSize {
+    /// Size of the header
+    pub header: u64,
+    /// Size of the name table
+    pub names: u64,
+    /// Size of the value tree
+    pub tree: u64,
+    /// Size of the string offsets and data
+    pub strings: u64,
+    /// Size of the resource offsets, lengths and data, padding included
+    pub resources: u64,
+    /// Size of the extra resource offsets, lengths and data, padding included
+    pub extra_resources: u64,
+}
+
+impl PsbSize {
+    /// Returns the size of the whole file.
+    pub const fn total(&self) -> u64 {
+        self.header + self.names + self.tree + self.strings + self.resources + self.extra_resources
+    }
+}
+
+/// Computes the size of the PSB file [`PsbWriter::new_with_buffer`] writes from
+/// `buf`, with resources of `resource_sizes` bytes, without writing it.
+pub fn estimate_size(version: u16, buf: &Buffer, resource_sizes: &[u64]) -> PsbSize {
+    estimate_size_with_options(&PsbWriterOptions::new(version), buf, resource_sizes, &[])
+}
+
+/// Computes the size of the PSB file [`PsbWriter::with_options_and_buffer`] writes
+/// from `buf`, with resources of `resource_sizes` bytes and extra resources of
+/// `extra_sizes` bytes, without writing it.
+///
+/// Resources collected in `buf` from [`PsbBytes`] values come before `resource_sizes`.
+/// Every resource is assumed to be written with its own data in the order it was
+/// added, i.e. without deduplication, removal or custom ordering.
+///
+/// [`PsbBytes`]: crate::value::PsbBytes
+pub fn estimate_size_with_options(
+    options: &PsbWriterOptions,
+    buf: &Buffer,
+    resource_sizes: &[u64],
+    extra_sizes: &[u64],
+) -> PsbSize {
+    let mut table = StringTable::new();
+    for name in buf.names() {
+        table.push_str(name);
+    }
+
+    let strings = buf.strings();
+    let string_data = strings
+        .iter()
+        .map(|string| string.len() as u64 + 1)
+        .sum::<u64>();
+    let last_offset = string_data - strings.last().map_or(0, |string| string.len() as u64 + 1);
+
+    let mut size = PsbSize {
+        header: header_length(options.version) as u64,
+        names: PsbBtree(table).encoded_len(options.btree_packing),
+        tree: buf.encoded_len(),
+        strings: uint_array_len(strings.len(), last_offset) + string_data,
+        resources: 0,
+        extra_resources: 0,
+    };
+
+    let alignment = options.resource_alignment;
+    let has_extra = options.version > 3;
+    if has_extra && options.extra_placement == ExtraPlacement::BeforeResources {
+        size.extra_resources = resources_len(size.total(), extra_sizes, alignment);
+    }
+    let resource_sizes = buf
+        .resources()
+        .iter()
+        .map(|data| data.len() as u64)
+        .chain(resource_sizes.iter().copied())
+        .collect::<Vec<_>>();
+    size.resources = resources_len(size.total(), &resource_sizes, alignment);
+    if has_extra && options.extra_placement == ExtraPlacement::AfterResources {
+        size.extra_resources = resources_len(size.total(), extra_sizes, alignment);
+    }
+    size
+}
+
+/// Returns the size of a resource section of `lengths` starting at `position`, as
+/// written by `Resources::write`.
+fn resources_len(position: u64, lengths: &[u64], alignment: u64) -> u64 {
+    let alignment = alignment.max(1);
+    let mut offset = 0;
+    let mut end = 0;
+    for &len in lengths {
+        offset = end;
+        end = (end + len).next_multiple_of(alignment);
+    }
+    let data_len = lengths.last().map_or(0, |&len| offset + len);
+
+    let arrays = uint_array_len(lengths.len(), offset)
+        + uint_array_len(lengths.len(), lengths.iter().copied().max().unwrap_or(0));
+    let data_start = (position + arrays).next_multiple_of(alignment);
+    data_start - position + data_len
+}
+
 /// A PSB file writer that serializes a root value and optional binary resources.
 ///
-/// Create with [`PsbWriter::new`] (or [`PsbWriter::new_with_buffer`] for a pre-built
-/// [`Buffer`]), then optionally attach binary resources via [`add_resource`] /
+/// Create with [`PsbWriter::new`] or [`PsbWriter::with_options`] (or
+/// [`PsbWriter::new_with_buffer`] for a pre-built [`Buffer`], and
+/// [`PsbWriter::with_options_and_spooled`] for a [`SpooledTree`]), then optionally attach binary resources via [`add_resource`] /
 /// [`add_extra`], and finally call [`finish`] to flush the complete file.
 ///
+/// Resources may borrow data for the lifetime `'a`.
+///
 /// [`add_resource`]: PsbWriter::add_resource
 /// [`add_extra`]: PsbWriter::add_extra
 /// [`finish`]: PsbWriter::finish
 #[derive(Debug)]
-pub struct PsbWriter<T> {
+pub struct PsbWriter<'a, T> {
     version: u16,
     offset_start: u64,
     header_length: u32,
 
     offsets: Offsets,
 
-    resources: Resources,
-    extra: Resources,
+    resources: Resources<'a>,
+    extra: Resources<'a>,
+    /// One past the highest resource index referenced by the value tree
+    resource_end: u64,
+    /// One past the highest extra resource index referenced by the value tree
+    extra_end: u64,
+    unused_resources: UnusedResources,
+    /// Resource references of the value tree, collected unless unused resources
+    /// are kept
+    refs: Vec<ResourceRef>,
+    /// Stream position of the value tree
+    tree_start: u64,
+    resource_alignment: u64,
+    extra_placement: ExtraPlacement,
+    resource_order: Option<ResourceOrder<'a>>,
 
     stream: PsbStream<T>,
 }
 
-impl<T> PsbWriter<T>
+impl<'a, T> PsbWriter<'a, T>
 where
     T: Write + Seek,
 {
@@ -61,10 +333,29 @@ where
         encrypted: bool,
         root: &impl Serialize,
         stream: T,
+    ) -> Result<Self, PsbWriteError> {
+        Self::with_options(
+            &PsbWriterOptions::new(version).encrypted(encrypted),
+            root,
+            stream,
+        )
+    }
+
+    /// Creates a new [`PsbWriter`] configured by `options`, serializing `root` and
+    /// writing the PSB header to `stream`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`PsbWriteError`] if serialization or writing the header fails.
+    pub fn with_options(
+        options: &PsbWriterOptions,
+        root: &impl Serialize,
+        stream: T,
     ) -> Result<Self, PsbWriteError> {
         let mut buf = Buffer::new();
+        buf.set_dedup(options.dedup);
         serialize(&root, &mut buf)?;
-        Self::new_with_buffer(version, encrypted, &mut buf, stream)
+        Self::with_options_and_buffer(options, &mut buf, stream)
     }
 
     /// Creates a new [`PsbWriter`] from a pre-populated serialization [`Buffer`].
@@ -84,6 +375,65 @@ where
         buf: &mut Buffer,
         stream: T,
     ) -> Result<Self, PsbWriteError> {
+        Self::with_options_and_buffer(
+            &PsbWriterOptions::new(version).encrypted(encrypted),
+            buf,
+            stream,
+        )
+    }
+
+    /// Creates a new [`PsbWriter`] configured by `options` from a pre-populated
+    /// serialization [`Buffer`].
+    ///
+    /// Serialization options such as [`PsbWriterOptions::dedup`] are taken from `buf`
+    /// instead, as it is already serialized.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`PsbWriteError`] if writing the header fails.
+    pub fn with_options_and_buffer(
+        options: &PsbWriterOptions,
+        buf: &mut Buffer,
+        stream: T,
+    ) -> Result<Self, PsbWriteError> {
+        Self::with_tree(options, buf, stream)
+    }
+
+    /// Creates a new [`PsbWriter`] configured by `options` from a value tree
+    /// serialized by [`serialize_spooled`].
+    ///
+    /// Serialization options such as [`PsbWriterOptions::dedup`] do not apply.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`PsbWriteError`] if reading the spool or writing the header fails.
+    ///
+    /// [`serialize_spooled`]: crate::value::ser::serialize_spooled
+    pub fn with_options_and_spooled<S: Read + Seek>(
+        options: &PsbWriterOptions,
+        tree: &mut SpooledTree<S>,
+        stream: T,
+    ) -> Result<Self, PsbWriteError> {
+        Self::with_tree(options, tree, stream)
+    }
+
+    fn with_tree(
+        options: &PsbWriterOptions,
+        tree: &mut impl EncodedTree,
+        stream: T,
+    ) -> Result<Self, PsbWriteError> {
+        let PsbWriterOptions {
+            version,
+            encrypted,
+            resource_hashing,
+            resource_dedup,
+            unused_resources,
+            resource_alignment,
+            extra_placement,
+            btree_packing,
+            ..
+        } = *options;
+
         let mut stream = PsbStream::new(stream)?;
         stream.write_u32::<LittleEndian>(PSB_SIGNATURE)?;
         stream.write_u16::<LittleEndian>(version)?;
@@ -98,14 +448,19 @@ where
         }
 
         let name_offset = stream.psb_position()?;
-        write_names(&mut stream, buf.names().iter())?;
+        write_names(&mut stream, btree_packing, tree.names().iter())?;
 
         let entrypoint = stream.psb_position()?;
-        buf.write(&mut stream)?;
+        let tree_start = stream.stream_position()?;
+        let mut refs = vec![];
+        tree.write_tree(
+            &mut stream,
+            (unused_resources != UnusedResources::Keep).then_some(&mut refs),
+        )?;
 
-        let mut offsets = Vec::<u64>::with_capacity(buf.strings().len());
+        let mut offsets = Vec::<u64>::with_capacity(tree.strings().len());
         let mut offset = 0;
-        for string in buf.strings() {
+        for string in tree.strings() {
             offsets.push(offset);
             offset += string.len() as u64 + 1;
         }
@@ -113,11 +468,17 @@ where
         write_uint_array(&mut stream, &offsets)?;
 
         let string_data_offset = stream.psb_position()?;
-        for string in buf.strings() {
+        for string in tree.strings() {
             stream.write_all(string.as_bytes())?;
             stream.write_u8(0)?;
         }
 
+        let (resource_end, extra_end) = tree.resource_end();
+        let mut resources = Resources::new(resource_hashing, resource_dedup);
+        for data in tree.take_resources() {
+            resources.add_referenced(data)?;
+        }
+
         Ok(Self {
             version,
             offset_start,
@@ -128,28 +489,140 @@ where
                 string_offsets: string_offsets_offset,
                 string_data: string_data_offset,
             },
-            resources: Resources::new(),
-            extra: Resources::new(),
+            resources,
+            extra: Resources::new(resource_hashing, resource_dedup),
+            resource_end,
+            extra_end,
+            unused_resources,
+            refs,
+            tree_start,
+            resource_alignment,
+            extra_placement,
+            resource_order: None,
             stream,
         })
     }
 
     /// Attaches a binary resource stream and returns its zero-based resource index.
     ///
+    /// Resources collected from [`PsbBytes`] values in the root take the first
+    /// indices, so attached resources are numbered after them.
+    ///
     /// The resource will be appended to the PSB resource section when [`finish`] is called.
+    /// With [`ResourceDedup::ReuseIndex`], the index of an identical existing resource
+    /// may be returned instead.
     ///
     /// [`finish`]: PsbWriter::finish
+    /// [`PsbBytes`]: crate::value::PsbBytes
     #[inline]
-    pub fn add_resource(&mut self, res: impl Read + Seek + 'static) -> io::Result<usize> {
-        self.resources.add(res)
+    pub fn add_resource(&mut self, res: impl Read + Seek + 'a) -> io::Result<usize> {
+        self.resources.add_stream(res)
+    }
+
+    /// Attaches binary resource data, either borrowed or owned, and returns its index.
+    #[inline]
+    pub fn add_resource_data(&mut self, data: impl Into<Cow<'a, [u8]>>) -> io::Result<usize> {
+        let data = data.into();
+        let len = data.len() as u64;
+        self.resources.add(Resource::Data(data), len)
+    }
+
+    /// Attaches a binary resource of `len` bytes produced by `producer`, and returns
+    /// its index.
+    ///
+    /// `producer` is invoked during [`finish`] to write the contents, which must be
+    /// exactly `len` bytes long. Produced resources have no content hash and are
+    /// never deduplicated.
+    ///
+    /// [`finish`]: PsbWriter::finish
+    #[inline]
+    pub fn add_resource_with(
+        &mut self,
+        len: u64,
+        producer: impl FnOnce(&mut dyn Write) -> io::Result<()> + 'a,
+    ) -> io::Result<usize> {
+        self.resources
+            .add(Resource::Producer(Box::new(producer)), len)
     }
 
     /// Attaches an extra (version 4+) binary resource stream and returns its index.
     ///
     /// Extra resources are written to the extra resource section introduced in PSB version 4.
+    /// Returns [`PsbWriteError::ExtraResourcesUnsupported`] for an earlier version.
+    #[inline]
+    pub fn add_extra(&mut self, res: impl Read + Seek + 'a) -> Result<usize, PsbWriteError> {
+        self.check_extra()?;
+        Ok(self.extra.add_stream(res)?)
+    }
+
+    /// Attaches extra binary resource data, either borrowed or owned, and returns its index.
+    ///
+    /// See [`add_extra`](PsbWriter::add_extra).
+    #[inline]
+    pub fn add_extra_data(
+        &mut self,
+        data: impl Into<Cow<'a, [u8]>>,
+    ) -> Result<usize, PsbWriteError> {
+        self.check_extra()?;
+        let data = data.into();
+        let len = data.len() as u64;
+        Ok(self.extra.add(Resource::Data(data), len)?)
+    }
+
+    /// Attaches an extra binary resource of `len` bytes produced by `producer`, and
+    /// returns its index.
+    ///
+    /// See [`add_resource_with`](PsbWriter::add_resource_with) and
+    /// [`add_extra`](PsbWriter::add_extra).
+    #[inline]
+    pub fn add_extra_with(
+        &mut self,
+        len: u64,
+        producer: impl FnOnce(&mut dyn Write) -> io::Result<()> + 'a,
+    ) -> Result<usize, PsbWriteError> {
+        self.check_extra()?;
+        Ok(self
+            .extra
+            .add(Resource::Producer(Box::new(producer)), len)?)
+    }
+
+    /// Fails if the PSB version has no extra resource section.
+    fn check_extra(&self) -> Result<(), PsbWriteError> {
+        if self.version <= 3 {
+            return Err(PsbWriteError::ExtraResourcesUnsupported(self.version));
+        }
+        Ok(())
+    }
+
+    /// Sets the order in which the data of resources is laid out, by comparing
+    /// resources with `compare`.
+    ///
+    /// Resource indexes are unchanged. Resources and extra resources are ordered
+    /// separately, and resources sharing data are placed with the first of them. By
+    /// default, data is laid out in index order.
+    pub fn order_resources_by(
+        &mut self,
+        compare: impl FnMut(&ResourceInfo, &ResourceInfo) -> Ordering + 'a,
+    ) {
+        self.resource_order = Some(ResourceOrder(Box::new(compare)));
+    }
+
+    /// Returns the content hash of the resource at `index`.
+    ///
+    /// Returns `None` if `index` is out of range, resource hashing is disabled, or the
+    /// resource is produced during [`finish`](PsbWriter::finish).
+    /// See [`PsbWriterOptions::resource_hashing`].
     #[inline]
-    pub fn add_extra(&mut self, res: impl Read + Seek + 'static) -> io::Result<usize> {
-        self.extra.add(res)
+    pub fn resource_hash(&self, index: usize) -> Option<u64> {
+        self.resources.hash(index)
+    }
+
+    /// Returns the content hash of the extra resource at `index`.
+    ///
+    /// Returns `None` under the same conditions as [`resource_hash`](PsbWriter::resource_hash).
+    #[inline]
+    pub fn extra_resource_hash(&self, index: usize) -> Option<u64> {
+        self.extra.hash(index)
     }
 
     /// Finalizes the PSB file by writing all resource data and updating the header offsets.
@@ -158,27 +631,47 @@ where
     ///
     /// # Errors
     ///
-    /// Returns an [`io::Error`] if any write or seek operation fails.
-    pub fn finish(mut self) -> io::Result<()> {
-        let extra_offsets = if self.version > 3 {
-            let extra_offset = self.stream.psb_position()?;
-            self.extra.write_offsets(&mut self.stream)?;
-            let extra_length = self.stream.psb_position()?;
-            self.extra.write_lengths(&mut self.stream)?;
-            let extra_data = self.stream.psb_position()?;
-            self.extra.write_data(&mut self.stream)?;
-
-            Some((extra_offset, extra_length, extra_data))
-        } else {
-            None
-        };
+    /// Returns [`PsbWriteError::Io`] if any write or seek operation fails, or if a
+    /// resource producer writes a different number of bytes than declared.
+    /// Returns an error before writing any resource if the resources do not match the
+    /// value tree, or cannot be encoded. The header, names and value tree were already
+    /// written by the constructor, so the output is left incomplete:
+    ///
+    /// - [`PsbWriteError::ResourceCountOverflow`] if more resources were added than
+    ///   can be indexed.
+    /// - [`PsbWriteError::MissingResource`] or [`PsbWriteError::MissingExtraResource`]
+    ///   if the value tree references a resource that was never added.
+    /// - [`PsbWriteError::UnusedResources`] if a resource is never referenced, with
+    ///   [`UnusedResources::Deny`].
+    ///
+    /// Returns [`PsbWriteError::OffsetOverflow`] if a section starts beyond 4 GiB.
+    pub fn finish(mut self) -> Result<(), PsbWriteError> {
+        self.validate()?;
+        self.check_unused()?;
+
+        let mut extra_offsets = None;
+        let has_extra = self.version > 3;
+        if has_extra && self.extra_placement == ExtraPlacement::BeforeResources {
+            extra_offsets = Some(self.extra.write(
+                &mut self.stream,
+                self.resource_alignment,
+                self.resource_order.as_mut(),
+            )?);
+        }
 
-        let resource_offset = self.stream.psb_position()?;
-        self.resources.write_offsets(&mut self.stream)?;
-        let resource_length = self.stream.psb_position()?;
-        self.resources.write_lengths(&mut self.stream)?;
-        let resource_data = self.stream.psb_position()?;
-        self.resources.write_data(&mut self.stream)?;
+        let (resource_offset, resource_length, resource_data) = self.resources.write(
+            &mut self.stream,
+            self.resource_alignment,
+            self.resource_order.as_mut(),
+        )?;
+
+        if has_extra && self.extra_placement == ExtraPlacement::AfterResources {
+            extra_offsets = Some(self.extra.write(
+                &mut self.stream,
+                self.resource_alignment,
+                self.resource_order.as_mut(),
+            )?);
+        }
 
         self.stream.seek(SeekFrom::Start(self.offset_start))?;
         self.write_offsets(
@@ -192,6 +685,66 @@ where
         Ok(())
     }
 
+    fn validate(&self) -> Result<(), PsbWriteError> {
+        for count in [self.resources.len(), self.extra.len()] {
+            if count as u64 > u32::MAX as u64 + 1 {
+                return Err(PsbWriteError::ResourceCountOverflow(count));
+            }
+        }
+
+        if self.resource_end > self.resources.len() as u64 {
+            return Err(PsbWriteError::MissingResource(self.resource_end - 1));
+        }
+        if self.extra_end > self.extra.len() as u64 {
+            return Err(PsbWriteError::MissingExtraResource(self.extra_end - 1));
+        }
+
+        Ok(())
+    }
+
+    /// Handles unused resources, then checks the extra resources are supported.
+    fn check_unused(&mut self) -> Result<(), PsbWriteError> {
+        if self.unused_resources != UnusedResources::Keep {
+            let mut used = vec![false; self.resources.len()];
+            let mut extra_used = vec![false; self.extra.len()];
+            for r in &self.refs {
+                let used = if r.extra { &mut extra_used } else { &mut used };
+                used[r.index as usize] = true;
+            }
+
+            let unused = |used: &[bool]| (0..used.len()).filter(|&i| !used[i]).collect();
+            let (resources, extra): (Vec<_>, Vec<_>) = (unused(&used), unused(&extra_used));
+            if self.unused_resources == UnusedResources::Deny
+                && (!resources.is_empty() || !extra.is_empty())
+            {
+                return Err(PsbWriteError::UnusedResources { resources, extra });
+            }
+
+            if !resources.is_empty() || !extra.is_empty() {
+                self.remove_unused(&used, &extra_used)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes unused resources and renumbers the references in the written tree.
+    fn remove_unused(&mut self, used: &[bool], extra_used: &[bool]) -> io::Result<()> {
+        let remap = self.resources.retain(used);
+        let extra_remap = self.extra.retain(extra_used);
+
+        for r in &self.refs {
+            let remap = if r.extra { &extra_remap } else { &remap };
+            let index = remap[r.index as usize] as u32;
+            self.stream
+                .seek(SeekFrom::Start(self.tree_start + r.offset as u64))?;
+            self.stream
+                .write_all(&index.to_le_bytes()[..r.width as usize])?;
+        }
+        self.stream.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+
     fn write_offsets(
         &mut self,
         resource_offset: u32,
@@ -274,8 +827,9 @@ where
         })
     }
 
-    pub fn psb_position(&mut self) -> io::Result<u32> {
-        Ok((self.inner.stream_position()? - self.start) as u32)
+    pub fn psb_position(&mut self) -> Result<u32, PsbWriteError> {
+        let position = self.inner.stream_position()? - self.start;
+        u32::try_from(position).map_err(|_| PsbWriteError::OffsetOverflow(position))
     }
 }
 
@@ -296,68 +850,382 @@ impl<T: Seek> Seek for PsbStream<T> {
 }
 
 #[derive(Debug)]
-struct Resources {
-    offsets: Vec<u64>,
+struct Resources<'a> {
     lengths: Vec<u64>,
-    streams: Vec<Resource>,
+    /// Content hash of each entry, `None` if hashing is disabled or not possible
+    hashes: Vec<Option<u64>>,
+    /// Entry holding the data of each entry, itself unless the data is shared
+    owners: Vec<usize>,
+    /// Data of each entry, `None` if it shares the data of another entry
+    data: Vec<Option<Resource<'a>>>,
+    /// Entries with unique data keyed by content hash and length
+    by_hash: HashMap<(u64, u64), Vec<usize>>,
+
+    hashing: bool,
+    dedup: ResourceDedup,
 }
 
-impl Resources {
+impl<'a> Resources<'a> {
     #[inline]
-    pub const fn new() -> Self {
+    pub fn new(hashing: bool, dedup: ResourceDedup) -> Self {
         Self {
-            offsets: vec![],
             lengths: vec![],
-            streams: vec![],
+            hashes: vec![],
+            owners: vec![],
+            data: vec![],
+            by_hash: HashMap::new(),
+            hashing: hashing || dedup != ResourceDedup::Disabled,
+            dedup,
         }
     }
 
-    pub fn add(&mut self, mut res: impl Read + Seek + 'static) -> io::Result<usize> {
-        let cur = res.stream_position()?;
-        let end = res.seek(SeekFrom::End(0))?;
-        res.seek(SeekFrom::Start(cur))?;
-        let size = end - cur;
+    pub fn add_stream(&mut self, mut stream: impl Read + Seek + 'a) -> io::Result<usize> {
+        let start = stream.stream_position()?;
+        let end = stream.seek(SeekFrom::End(0))?;
+        stream.seek(SeekFrom::Start(start))?;
 
-        let id = self.offsets.len();
-        self.offsets.push({
-            let last_offset = self.offsets.last().copied().unwrap_or_default();
-            let last_size = self.lengths.last().copied().unwrap_or_default();
+        self.add(
+            Resource::Stream {
+                start,
+                stream: Box::new(stream),
+            },
+            end - start,
+        )
+    }
 
-            last_offset + last_size
-        });
-        self.lengths.push(size);
-        self.streams.push(Resource(Box::new(res)));
+    pub fn add(&mut self, mut res: Resource<'a>, size: u64) -> io::Result<usize> {
+        let id = self.lengths.len();
+        let hash = if self.hashing { res.hash()? } else { None };
+
+        if let (Some(hash), ResourceDedup::ReuseIndex | ResourceDedup::ShareData) =
+            (hash, self.dedup)
+        {
+            let candidates = self
+                .by_hash
+                .get(&(hash, size))
+                .map_or(&[][..], Vec::as_slice);
+            for &existing in candidates {
+                let Some(ref mut other) = self.data[existing] else {
+                    continue;
+                };
+
+                if other.content_eq(&mut res)? {
+                    if self.dedup == ResourceDedup::ReuseIndex {
+                        return Ok(existing);
+                    }
+
+                    self.lengths.push(size);
+                    self.hashes.push(Some(hash));
+                    self.owners.push(existing);
+                    self.data.push(None);
+                    return Ok(id);
+                }
+            }
 
+            self.by_hash.entry((hash, size)).or_default().push(id);
+        }
+
+        self.lengths.push(size);
+        self.hashes.push(hash);
+        self.owners.push(id);
+        self.data.push(Some(res));
         Ok(id)
     }
 
-    pub fn write_offsets(&self, stream: &mut impl Write) -> io::Result<()> {
-        write_uint_array(stream, &self.offsets)?;
-        Ok(())
+    /// Adds `data` already referenced by its index, sharing the data of an identical
+    /// resource instead of reusing its index.
+    pub fn add_referenced(&mut self, data: Vec<u8>) -> io::Result<usize> {
+        let dedup = self.dedup;
+        if dedup == ResourceDedup::ReuseIndex {
+            self.dedup = ResourceDedup::ShareData;
+        }
+
+        let len = data.len() as u64;
+        let res = self.add(Resource::Data(Cow::Owned(data)), len);
+        self.dedup = dedup;
+        res
     }
 
-    pub fn write_lengths(&self, stream: &mut impl Write) -> io::Result<()> {
-        write_uint_array(stream, &self.lengths)?;
-        Ok(())
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.lengths.len()
     }
 
-    pub fn write_data(&mut self, stream: &mut impl Write) -> io::Result<()> {
-        for Resource(data) in &mut self.streams {
-            io::copy(data, stream)?;
+    /// Keeps the entries marked in `keep`, returning the new index of each entry.
+    ///
+    /// Data shared with a removed entry moves to the first kept entry sharing it.
+    pub fn retain(&mut self, keep: &[bool]) -> Vec<usize> {
+        let mut remap = vec![usize::MAX; keep.len()];
+        let mut lengths = vec![];
+        let mut hashes = vec![];
+        let mut owners = vec![];
+        let mut data = vec![];
+        // Data of removed owners, and the kept entry it moved to
+        let mut orphaned = HashMap::new();
+        let mut moved = HashMap::new();
+
+        for (i, res) in mem::take(&mut self.data).into_iter().enumerate() {
+            let owner = self.owners[i];
+            if !keep[i] {
+                if let Some(res) = res {
+                    orphaned.insert(i, res);
+                }
+                continue;
+            }
+
+            let id = lengths.len();
+            let (owner, res) = if owner == i {
+                (id, res)
+            } else if keep[owner] {
+                (remap[owner], None)
+            } else if let Some(&new_owner) = moved.get(&owner) {
+                (new_owner, None)
+            } else {
+                moved.insert(owner, id);
+                (id, orphaned.remove(&owner))
+            };
+
+            remap[i] = id;
+            lengths.push(self.lengths[i]);
+            hashes.push(self.hashes[i]);
+            owners.push(owner);
+            data.push(res);
         }
-        Ok(())
+
+        self.lengths = lengths;
+        self.hashes = hashes;
+        self.owners = owners;
+        self.data = data;
+        self.by_hash.clear();
+        remap
+    }
+
+    #[inline]
+    pub fn hash(&self, index: usize) -> Option<u64> {
+        self.hashes.get(index).copied().flatten()
     }
+
+    /// Orders the entries holding data, by `order` if given.
+    fn data_order(&self, order: Option<&mut ResourceOrder<'_>>) -> Vec<usize> {
+        let mut entries = (0..self.len())
+            .filter(|&i| self.owners[i] == i)
+            .collect::<Vec<_>>();
+        if let Some(order) = order {
+            entries.sort_by(|&a, &b| (order.0)(&self.info(a), &self.info(b)));
+        }
+        entries
+    }
+
+    fn info(&self, index: usize) -> ResourceInfo {
+        ResourceInfo {
+            index,
+            len: self.lengths[index],
+            hash: self.hashes[index],
+        }
+    }
+
+    /// Writes the offset, length and data arrays, returning their positions.
+    ///
+    /// The data of each entry is placed in `order`, starting at an offset aligned to
+    /// `alignment` from the start of the PSB file.
+    pub fn write<T: Write + Seek>(
+        &mut self,
+        stream: &mut PsbStream<T>,
+        alignment: u64,
+        order: Option<&mut ResourceOrder<'_>>,
+    ) -> Result<(u32, u32, u32), PsbWriteError> {
+        let alignment = alignment.max(1);
+        let order = self.data_order(order);
+
+        // Offsets are relative to the data section, which starts aligned.
+        let mut offsets = vec![0; self.len()];
+        let mut end = 0;
+        for &i in &order {
+            offsets[i] = end;
+            end = (end + self.lengths[i]).next_multiple_of(alignment);
+        }
+        for i in 0..self.len() {
+            offsets[i] = offsets[self.owners[i]];
+        }
+
+        let offsets_position = stream.psb_position()?;
+        write_uint_array(stream, &offsets)?;
+        let lengths_position = stream.psb_position()?;
+        write_uint_array(stream, &self.lengths)?;
+
+        let position = stream.psb_position()? as u64;
+        write_padding(stream, position.next_multiple_of(alignment) - position)?;
+        let data_position = stream.psb_position()?;
+
+        let mut written_end = 0;
+        for i in order {
+            write_padding(stream, offsets[i] - written_end)?;
+
+            let len = self.lengths[i];
+            let res = self.data[i].take().expect("resource data written twice");
+            let written = res.write_to(stream)?;
+            if written != len {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("resource produced {written} bytes, expected {len}"),
+                )
+                .into());
+            }
+            written_end = offsets[i] + len;
+        }
+
+        Ok((offsets_position, lengths_position, data_position))
+    }
+}
+
+fn write_padding(stream: &mut impl Write, len: u64) -> io::Result<()> {
+    io::copy(&mut io::repeat(0).take(len), stream)?;
+    Ok(())
+}
+
+/// Comparison callback set by [`PsbWriter::order_resources_by`].
+struct ResourceOrder<'a>(ResourceCompare<'a>);
+
+type ResourceCompare<'a> = Box<dyn FnMut(&ResourceInfo, &ResourceInfo) -> Ordering + 'a>;
+
+impl Debug for ResourceOrder<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResourceOrder").finish_non_exhaustive()
+    }
+}
+
+trait ResourceStream: Read + Seek {}
+
+impl<T: Read + Seek> ResourceStream for T {}
+
+type ResourceProducer<'a> = Box<dyn FnOnce(&mut dyn Write) -> io::Result<()> + 'a>;
+
+enum Resource<'a> {
+    Stream {
+        start: u64,
+        stream: Box<dyn ResourceStream + 'a>,
+    },
+    Data(Cow<'a, [u8]>),
+    Producer(ResourceProducer<'a>),
 }
 
-#[repr(transparent)]
-struct Resource(Box<dyn Read>);
+impl Resource<'_> {
+    /// Returns a reader over the contents from the start, or `None` if the contents
+    /// are only available when written.
+    fn reader(&mut self) -> io::Result<Option<Box<dyn Read + '_>>> {
+        Ok(match self {
+            Resource::Stream { start, stream } => {
+                stream.seek(SeekFrom::Start(*start))?;
+                Some(Box::new(stream))
+            }
+            Resource::Data(data) => Some(Box::new(&data[..])),
+            Resource::Producer(_) => None,
+        })
+    }
+
+    /// Computes the content hash, or `None` for produced resources.
+    fn hash(&mut self) -> io::Result<Option<u64>> {
+        if let Resource::Data(data) = self {
+            return Ok(Some(XxHash3_64::oneshot(data)));
+        }
+
+        let Some(mut reader) = self.reader()? else {
+            return Ok(None);
+        };
+        let mut hasher = XxHash3_64::new();
+        let mut buf = [0_u8; 8192];
+        loop {
+            let read = reader.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            hasher.write(&buf[..read]);
+        }
+
+        Ok(Some(hasher.finish()))
+    }
 
-impl Debug for Resource {
+    /// Compares contents of two resources of the same length.
+    fn content_eq(&mut self, other: &mut Resource) -> io::Result<bool> {
+        let (Some(mut a), Some(mut b)) = (self.reader()?, other.reader()?) else {
+            return Ok(false);
+        };
+
+        let mut buf_a = [0_u8; 8192];
+        let mut buf_b = [0_u8; 8192];
+        loop {
+            let read = a.read(&mut buf_a)?;
+            if read == 0 {
+                return Ok(true);
+            }
+            b.read_exact(&mut buf_b[..read])?;
+            if buf_a[..read] != buf_b[..read] {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Writes the contents to `stream`, returning the number of bytes written.
+    fn write_to(mut self, stream: &mut impl Write) -> io::Result<u64> {
+        if let Resource::Producer(producer) = self {
+            let mut stream = CountingWriter {
+                inner: stream,
+                count: 0,
+            };
+            producer(&mut stream)?;
+            return Ok(stream.count);
+        }
+
+        match self.reader()? {
+            Some(mut reader) => io::copy(&mut reader, stream),
+            None => Ok(0),
+        }
+    }
+}
+
+impl Debug for Resource<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Resource").finish_non_exhaustive()
+        match self {
+            Resource::Stream { start, .. } => f
+                .debug_struct("Stream")
+                .field("start", start)
+                .finish_non_exhaustive(),
+            Resource::Data(data) => f.debug_tuple("Data").field(&data.len()).finish(),
+            Resource::Producer(_) => f.debug_struct("Producer").finish_non_exhaustive(),
+        }
+    }
+}
+
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
     }
 }
 
+/// How [`PsbWriter`] handles resources with identical contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ResourceDedup {
+    /// Every added resource gets its own index and data.
+    #[default]
+    Disabled,
+    /// Adding a resource identical to an existing one returns the existing index.
+    ReuseIndex,
+    /// Adding a resource identical to an existing one returns a new index whose
+    /// offset entry points at the existing data.
+    ShareData,
+}
+
 #[derive(Debug)]
 struct Offsets {
     name: u32,
@@ -376,6 +1244,7 @@ const fn header_length(version: u16) -> u32 {
 
 fn write_names<'a>(
     stream: &mut impl Write,
+    packing: BtreePacking,
     names: impl Iterator<Item = &'a SmolStr>,
 ) -> io::Result<()> {
     let mut table = StringTable::new();
@@ -383,6 +1252,6 @@ fn write_names<'a>(
         table.push_str(name);
     }
     let btree = PsbBtree(table);
-    btree.write_tree(stream)?;
+    btree.write_tree(packing, stream)?;
     Ok(())
 }
@@ -14,6 +14,14 @@ pub enum Error {
     #[error("invalid psb value")]
     InvalidValue,
 
+    /// A resource index is out of range of the resources of the file.
+    #[error("missing psb resource: {0}")]
+    MissingResource(u32),
+
+    /// An extra resource index is out of range of the extra resources of the file.
+    #[error("missing psb extra resource: {0}")]
+    MissingExtraResource(u32),
+
     /// An I/O error occurred while reading the stream.
     #[error(transparent)]
     Io(#[from] io::Error),
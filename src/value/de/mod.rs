@@ -4,27 +4,32 @@ mod error;
 mod map;
 mod seq;
 mod special;
+mod variant;
 
 pub use error::Error;
 
 use core::ops::Range;
-use std::io::{BufRead, ErrorKind, Seek};
+use std::io::{BufRead, ErrorKind, Seek, SeekFrom};
 
 use byteorder::{LittleEndian, ReadBytesExt};
-use serde::forward_to_deserialize_any;
+use serde::{
+    de::{Error as _, IntoDeserializer, value::SeqDeserializer},
+    forward_to_deserialize_any,
+};
 
 use crate::{
-    psb::table::StringTable,
+    psb::{read::PsbResourceItem, table::StringTable},
     value::{
         PSB_COMPILER_ARRAY, PSB_COMPILER_BINARY_TREE, PSB_COMPILER_BOOL, PSB_COMPILER_DECIMAL,
         PSB_COMPILER_INTEGER, PSB_COMPILER_RESOURCE, PSB_COMPILER_STRING, PSB_TYPE_DOUBLE,
-        PSB_TYPE_EXTRA_N, PSB_TYPE_FALSE, PSB_TYPE_FLOAT, PSB_TYPE_FLOAT0, PSB_TYPE_INTEGER_N,
-        PSB_TYPE_LIST, PSB_TYPE_NULL, PSB_TYPE_OBJECT, PSB_TYPE_RESOURCE_N, PSB_TYPE_STRING_N,
-        PSB_TYPE_TRUE, PsbCompilerArray, PsbCompilerBinaryTree, PsbCompilerBool,
-        PsbCompilerDecimal, PsbCompilerNumber, PsbCompilerResource, PsbCompilerString,
-        PsbExtraResource, PsbResource,
-        de::{map::PsbObject, seq::List, special::SpecialTypeDeserializer},
-        util::{read_partial_int, read_partial_uint, read_uint_array},
+        PSB_TYPE_EXTRA_N, PSB_TYPE_FALSE, PSB_TYPE_FLOAT, PSB_TYPE_FLOAT0,
+        PSB_TYPE_INTEGER_ARRAY_N, PSB_TYPE_INTEGER_N, PSB_TYPE_LIST, PSB_TYPE_NULL,
+        PSB_TYPE_OBJECT, PSB_TYPE_RESOURCE_N, PSB_TYPE_STRING_N, PSB_TYPE_TRUE, PsbBytes,
+        PsbCompilerArray, PsbCompilerBinaryTree, PsbCompilerBool, PsbCompilerDecimal,
+        PsbCompilerNumber, PsbCompilerResource, PsbCompilerString, PsbExtraResource, PsbIntArray,
+        PsbResource,
+        de::{map::PsbObject, seq::List, special::SpecialTypeDeserializer, variant::Variant},
+        util::{read_partial_int, read_partial_uint, read_uint_array, read_uint_array_items},
     },
 };
 
@@ -35,6 +40,9 @@ use crate::{
 pub struct Deserializer<'a, T> {
     names: &'a StringTable,
     strings: &'a StringTable,
+    resources: &'a [PsbResourceItem],
+    extra: &'a [PsbResourceItem],
+    resolve_resources: bool,
     buf: Vec<u64>,
     stream: T,
 }
@@ -45,11 +53,36 @@ impl<'a, T: BufRead + Seek> Deserializer<'a, T> {
         Self {
             names,
             strings,
+            resources: &[],
+            extra: &[],
+            resolve_resources: false,
             buf: vec![],
             stream,
         }
     }
 
+    /// Sets the resources and extra resources [`PsbBytes`] values are loaded from.
+    pub(crate) const fn with_resources(
+        mut self,
+        resources: &'a [PsbResourceItem],
+        extra: &'a [PsbResourceItem],
+    ) -> Self {
+        self.resources = resources;
+        self.extra = extra;
+        self
+    }
+
+    /// Sets whether byte sequences (e.g. `Vec<u8>`) are read from resource and extra
+    /// resource references, receiving the resource data instead of failing on the
+    /// reference.
+    ///
+    /// [`PsbBytes`] values are always loaded. Self-describing targets such as
+    /// [`PsbValue`](crate::value::PsbValue) still receive the resource index.
+    pub const fn resolve_resources(mut self, resolve: bool) -> Self {
+        self.resolve_resources = resolve;
+        self
+    }
+
     fn peek_ty(&mut self) -> Result<u8, Error> {
         self.stream
             .fill_buf()?
@@ -58,6 +91,77 @@ impl<'a, T: BufRead + Seek> Deserializer<'a, T> {
             .ok_or(Error::Io(ErrorKind::UnexpectedEof.into()))
     }
 
+    /// Reads the items of an integer array value, if the next value is one.
+    fn read_int_array(&mut self) -> Result<Option<Vec<u64>>, Error> {
+        const PSB_TYPE_INTEGER_ARRAY_START: u8 = PSB_TYPE_INTEGER_ARRAY_N + 1;
+        const PSB_TYPE_INTEGER_ARRAY_MAX: u8 = PSB_TYPE_INTEGER_ARRAY_N + 8;
+
+        match self.peek_ty()? {
+            value_type @ PSB_TYPE_INTEGER_ARRAY_START..=PSB_TYPE_INTEGER_ARRAY_MAX => {
+                self.stream.consume(1);
+                let mut items = vec![];
+                read_uint_array_items(
+                    &mut self.stream,
+                    value_type - PSB_TYPE_INTEGER_ARRAY_N,
+                    &mut items,
+                )?;
+                Ok(Some(items))
+            }
+
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads the data of a resource or extra resource reference, if the next value
+    /// is one.
+    fn read_resource_data(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        const PSB_TYPE_RESOURCE_START: u8 = PSB_TYPE_RESOURCE_N + 1;
+        const PSB_TYPE_RESOURCE_MAX: u8 = PSB_TYPE_RESOURCE_N + 4;
+        const PSB_TYPE_EXTRA_START: u8 = PSB_TYPE_EXTRA_N + 1;
+        const PSB_TYPE_EXTRA_MAX: u8 = PSB_TYPE_EXTRA_N + 4;
+
+        let (value_type, n) = match self.peek_ty()? {
+            value_type @ PSB_TYPE_RESOURCE_START..=PSB_TYPE_RESOURCE_MAX => {
+                (value_type, value_type - PSB_TYPE_RESOURCE_N)
+            }
+            value_type @ PSB_TYPE_EXTRA_START..=PSB_TYPE_EXTRA_MAX => {
+                (value_type, value_type - PSB_TYPE_EXTRA_N)
+            }
+            _ => return Ok(None),
+        };
+
+        self.stream.consume(1);
+        let idx: u32 = read_partial_uint(&mut self.stream, n)?
+            .try_into()
+            .map_err(|_| Error::InvalidValue)?;
+        let res = if value_type <= PSB_TYPE_RESOURCE_MAX {
+            self.resources
+                .get(idx as usize)
+                .ok_or(Error::MissingResource(idx))?
+        } else {
+            self.extra
+                .get(idx as usize)
+                .ok_or(Error::MissingExtraResource(idx))?
+        };
+
+        let position = self.stream.stream_position()?;
+        self.stream.seek(SeekFrom::Start(res.position))?;
+        let mut data = vec![0; res.size as usize];
+        self.stream.read_exact(&mut data)?;
+        self.stream.seek(SeekFrom::Start(position))?;
+        Ok(Some(data))
+    }
+
+    /// Reads the data of a resource reference in place of its index, if enabled and
+    /// the next value is one.
+    fn read_resolved_data(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        if self.resolve_resources {
+            self.read_resource_data()
+        } else {
+            Ok(None)
+        }
+    }
+
     fn read_uint_array_buf(&mut self) -> Result<Range<usize>, Error> {
         let start = self.buf.len();
         let len = read_uint_array(&mut self.stream, &mut self.buf)?;
@@ -76,6 +180,8 @@ impl<T: BufRead + Seek> serde::Deserializer<'static> for &mut Deserializer<'_, T
         const PSB_TYPE_INTEGER_MAX: u8 = PSB_TYPE_INTEGER_N + 8;
         const PSB_TYPE_RESOURCE_START: u8 = PSB_TYPE_RESOURCE_N + 1;
         const PSB_TYPE_RESOURCE_MAX: u8 = PSB_TYPE_RESOURCE_N + 4;
+        const PSB_TYPE_INTEGER_ARRAY_START: u8 = PSB_TYPE_INTEGER_ARRAY_N + 1;
+        const PSB_TYPE_INTEGER_ARRAY_MAX: u8 = PSB_TYPE_INTEGER_ARRAY_N + 8;
         const PSB_TYPE_STRING_START: u8 = PSB_TYPE_STRING_N + 1;
         const PSB_TYPE_STRING_MAX: u8 = PSB_TYPE_STRING_N + 4;
         const PSB_TYPE_EXTRA_START: u8 = PSB_TYPE_EXTRA_N + 1;
@@ -95,6 +201,17 @@ impl<T: BufRead + Seek> serde::Deserializer<'static> for &mut Deserializer<'_, T
                 read_partial_int(&mut self.stream, value_type - PSB_TYPE_INTEGER_N)?,
             ),
 
+            value_type @ PSB_TYPE_INTEGER_ARRAY_START..=PSB_TYPE_INTEGER_ARRAY_MAX => {
+                let mut items = vec![];
+                read_uint_array_items(
+                    &mut self.stream,
+                    value_type - PSB_TYPE_INTEGER_ARRAY_N,
+                    &mut items,
+                )?;
+
+                SpecialTypeDeserializer::new(PsbIntArray::MARKER, items).deserialize(visitor)
+            }
+
             value_type @ PSB_TYPE_STRING_START..=PSB_TYPE_STRING_MAX => {
                 let idx: u32 = read_partial_uint(&mut self.stream, value_type - PSB_TYPE_STRING_N)?
                     .try_into()
@@ -168,8 +285,119 @@ impl<T: BufRead + Seek> serde::Deserializer<'static> for &mut Deserializer<'_, T
     forward_to_deserialize_any! {
         <W: Visitor<'static>>
         bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
-        bytes byte_buf unit unit_struct seq tuple
-        tuple_struct map struct enum identifier
+        unit unit_struct tuple_struct map identifier
+    }
+
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: serde::de::Visitor<'static>,
+    {
+        self.deserialize_byte_buf(visitor)
+    }
+
+    /// Reads the data of a resource reference when resolving resources, otherwise a
+    /// sequence.
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: serde::de::Visitor<'static>,
+    {
+        match self.read_resolved_data()? {
+            Some(data) => visitor.visit_byte_buf(data),
+            None => self.deserialize_seq(visitor),
+        }
+    }
+
+    /// Reads a struct, loading the data of a resource or extra resource reference for
+    /// [`PsbBytes`].
+    fn deserialize_struct<V>(
+        self,
+        name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: serde::de::Visitor<'static>,
+    {
+        if name == PsbBytes::MARKER
+            && let Some(data) = self.read_resource_data()?
+        {
+            return SpecialTypeDeserializer::new(PsbBytes::MARKER, data).deserialize(visitor);
+        }
+
+        self.deserialize_any(visitor)
+    }
+
+    /// Reads a list, or an integer array as a sequence of its items.
+    ///
+    /// When resolving resources, a resource reference is read as its data.
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: serde::de::Visitor<'static>,
+    {
+        if let Some(data) = self.read_resolved_data()? {
+            return visitor.visit_seq(SeqDeserializer::new(data.into_iter()));
+        }
+
+        match self.read_int_array()? {
+            Some(items) => visitor.visit_seq(SeqDeserializer::new(items.into_iter())),
+            None => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: serde::de::Visitor<'static>,
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    /// Reads an enum, either a string naming a unit variant or an object with the
+    /// variant name as its single key.
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: serde::de::Visitor<'static>,
+    {
+        const PSB_TYPE_STRING_START: u8 = PSB_TYPE_STRING_N + 1;
+        const PSB_TYPE_STRING_MAX: u8 = PSB_TYPE_STRING_N + 4;
+
+        match self.peek_ty()? {
+            value_type @ PSB_TYPE_STRING_START..=PSB_TYPE_STRING_MAX => {
+                self.stream.consume(1);
+                let idx: u32 = read_partial_uint(&mut self.stream, value_type - PSB_TYPE_STRING_N)?
+                    .try_into()
+                    .map_err(|_| Error::InvalidValue)?;
+
+                let strings = self.strings;
+                let variant = strings.get(idx as _).ok_or(Error::InvalidValue)?;
+                visitor.visit_enum(variant.into_deserializer())
+            }
+
+            PSB_TYPE_OBJECT => {
+                self.stream.consume(1);
+                let names = self.read_uint_array_buf()?;
+                let buf_start = names.start;
+                let offsets = self.read_uint_array_buf()?;
+                let data_start = self.stream.stream_position()?;
+                let entry = (names.len() == 1 && offsets.len() == 1)
+                    .then(|| (self.buf[names.start], data_start + self.buf[offsets.start]));
+                self.buf.drain(buf_start..);
+
+                let Some((name, position)) = entry else {
+                    return Err(Error::invalid_length(
+                        names.len(),
+                        &"an object with a single key",
+                    ));
+                };
+                visitor.visit_enum(Variant::new(self, name, position))
+            }
+
+            _ => self.deserialize_any(visitor),
+        }
     }
 
     fn deserialize_newtype_struct<V>(
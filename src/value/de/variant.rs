@@ -0,0 +1,84 @@
+use std::io::{BufRead, Seek, SeekFrom};
+
+use serde::{
+    Deserializer as _,
+    de::{EnumAccess, IntoDeserializer, VariantAccess},
+};
+
+use crate::value::de::{Deserializer, error};
+
+/// Variant of an enum, stored as an object with the variant name as its single key.
+pub struct Variant<'a, 'b, T> {
+    name: u64,
+    position: u64,
+    inner: &'b mut Deserializer<'a, T>,
+}
+
+impl<'a, 'b, T> Variant<'a, 'b, T> {
+    pub const fn new(inner: &'b mut Deserializer<'a, T>, name: u64, position: u64) -> Self {
+        Self {
+            name,
+            position,
+            inner,
+        }
+    }
+}
+
+impl<'a, 'b, T> EnumAccess<'static> for Variant<'a, 'b, T>
+where
+    T: BufRead + Seek,
+{
+    type Error = error::Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error>
+    where
+        V: serde::de::DeserializeSeed<'static>,
+    {
+        let name = self
+            .inner
+            .names
+            .get(self.name as _)
+            .ok_or(error::Error::InvalidValue)?;
+
+        let value = seed.deserialize(IntoDeserializer::<error::Error>::into_deserializer(name))?;
+        self.inner.stream.seek(SeekFrom::Start(self.position))?;
+        Ok((value, self))
+    }
+}
+
+impl<'a, 'b, T> VariantAccess<'static> for Variant<'a, 'b, T>
+where
+    T: BufRead + Seek,
+{
+    type Error = error::Error;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<V>(self, seed: V) -> Result<V::Value, Self::Error>
+    where
+        V: serde::de::DeserializeSeed<'static>,
+    {
+        seed.deserialize(self.inner)
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: serde::de::Visitor<'static>,
+    {
+        self.inner.deserialize_seq(visitor)
+    }
+
+    fn struct_variant<V>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: serde::de::Visitor<'static>,
+    {
+        self.inner.deserialize_map(visitor)
+    }
+}
@@ -1,8 +1,9 @@
-use std::collections::HashMap;
+use core::marker::PhantomData;
 
 use serde::{
     Deserialize, Serialize,
     de::{DeserializeSeed, Unexpected, Visitor},
+    ser::SerializeStruct,
 };
 use smol_str::SmolStr;
 
@@ -50,6 +51,12 @@ impl From<PsbExtraResource> for PsbValue {
     }
 }
 
+impl<T: Into<u64>> From<PsbIntArray<T>> for PsbValue {
+    fn from(v: PsbIntArray<T>) -> Self {
+        Self::IntArray(v.0.into_iter().map(Into::into).collect())
+    }
+}
+
 impl From<PsbCompilerNumber> for PsbValue {
     fn from(_: PsbCompilerNumber) -> Self {
         Self::CompilerNumber
@@ -98,23 +105,37 @@ impl Serialize for PsbValue {
     where
         S: serde::Serializer,
     {
-        match *self {
-            PsbValue::Null => se.serialize_none(),
-            PsbValue::Bool(v) => se.serialize_bool(v),
-            PsbValue::Number(v) => v.serialize(se),
-            PsbValue::String(ref v) => v.serialize(se),
-            PsbValue::Resource(v) => PsbResource(v).serialize(se),
-            PsbValue::ExtraResource(v) => PsbExtraResource(v).serialize(se),
-            PsbValue::List(ref v) => v.serialize(se),
-            PsbValue::CompilerNumber => PsbCompilerNumber.serialize(se),
-            PsbValue::CompilerString => PsbCompilerString.serialize(se),
-            PsbValue::CompilerResource => PsbCompilerResource.serialize(se),
-            PsbValue::CompilerDecimal => PsbCompilerDecimal.serialize(se),
-            PsbValue::CompilerArray => PsbCompilerArray.serialize(se),
-            PsbValue::CompilerBool => PsbCompilerBool.serialize(se),
-            PsbValue::CompilerBinaryTree => PsbCompilerBinaryTree.serialize(se),
-            PsbValue::Object(ref v) => v.serialize(se),
+        #[cfg(feature = "rayon")]
+        if let Some(encoded) = ser::par::encode(self) {
+            return ser::par::serialize_encoded(encoded, self, se);
         }
+
+        serialize_plain(self, se)
+    }
+}
+
+/// Serializes `value` on the calling thread.
+pub(crate) fn serialize_plain<S>(value: &PsbValue, se: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match *value {
+        PsbValue::Null => se.serialize_none(),
+        PsbValue::Bool(v) => se.serialize_bool(v),
+        PsbValue::Number(v) => v.serialize(se),
+        PsbValue::String(ref v) => v.serialize(se),
+        PsbValue::Resource(v) => PsbResource(v).serialize(se),
+        PsbValue::ExtraResource(v) => PsbExtraResource(v).serialize(se),
+        PsbValue::List(ref v) => v.serialize(se),
+        PsbValue::IntArray(ref v) => serialize_int_array(v, se),
+        PsbValue::CompilerNumber => PsbCompilerNumber.serialize(se),
+        PsbValue::CompilerString => PsbCompilerString.serialize(se),
+        PsbValue::CompilerResource => PsbCompilerResource.serialize(se),
+        PsbValue::CompilerDecimal => PsbCompilerDecimal.serialize(se),
+        PsbValue::CompilerArray => PsbCompilerArray.serialize(se),
+        PsbValue::CompilerBool => PsbCompilerBool.serialize(se),
+        PsbValue::CompilerBinaryTree => PsbCompilerBinaryTree.serialize(se),
+        PsbValue::Object(ref v) => v.serialize(se),
     }
 }
 
@@ -208,6 +229,7 @@ impl<'de> Deserialize<'de> for PsbValue {
                 match map.next_key_seed(KeyClassifier)? {
                     Some(KeyClass::Resource) => Ok(PsbValue::Resource(map.next_value()?)),
                     Some(KeyClass::ExtraResource) => Ok(PsbValue::ExtraResource(map.next_value()?)),
+                    Some(KeyClass::IntArray) => Ok(PsbValue::IntArray(map.next_value()?)),
 
                     Some(KeyClass::CompilerNumber) => Ok(PsbValue::CompilerNumber),
                     Some(KeyClass::CompilerString) => Ok(PsbValue::CompilerString),
@@ -219,7 +241,7 @@ impl<'de> Deserialize<'de> for PsbValue {
 
                     Some(KeyClass::Object(first_key)) => {
                         let mut object =
-                            HashMap::with_capacity(map.size_hint().unwrap_or_default());
+                            IndexMap::with_capacity(map.size_hint().unwrap_or_default());
                         object.insert(first_key, map.next_value()?);
                         while let Some((key, value)) = map.next_entry()? {
                             object.insert(key, value);
@@ -227,7 +249,7 @@ impl<'de> Deserialize<'de> for PsbValue {
 
                         Ok(PsbValue::Object(object))
                     }
-                    None => Ok(PsbValue::Object(HashMap::new())),
+                    None => Ok(PsbValue::Object(IndexMap::new())),
                 }
             }
 
@@ -260,6 +282,109 @@ impl<'de> Deserialize<'de> for PsbValue {
     }
 }
 
+impl<T: Serialize> Serialize for PsbIntArray<T> {
+    fn serialize<S>(&self, se: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serialize_int_array(&self.0, se)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for PsbIntArray<T> {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct IntArrayVisitor<T>(PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>> Visitor<'de> for IntArrayVisitor<T> {
+            type Value = PsbIntArray<T>;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("an integer array or a sequence")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                let mut vec = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+                while let Some(elem) = seq.next_element()? {
+                    vec.push(elem);
+                }
+
+                Ok(PsbIntArray(vec))
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                match map.next_key_seed(KeyClassifier)? {
+                    Some(KeyClass::IntArray) => Ok(PsbIntArray(map.next_value()?)),
+                    _ => Err(serde::de::Error::invalid_type(Unexpected::Map, &self)),
+                }
+            }
+        }
+
+        de.deserialize_struct(
+            PsbIntArray::MARKER,
+            &[PsbIntArray::MARKER],
+            IntArrayVisitor(PhantomData),
+        )
+    }
+}
+
+impl Serialize for PsbBytes {
+    fn serialize<S>(&self, se: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        struct Bytes<'a>(&'a [u8]);
+
+        impl Serialize for Bytes<'_> {
+            fn serialize<S>(&self, se: S) -> Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                se.serialize_bytes(self.0)
+            }
+        }
+
+        let mut st = se.serialize_struct(PsbBytes::MARKER, 1)?;
+        st.serialize_field(PsbBytes::MARKER, &Bytes(&self.0))?;
+        st.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for PsbBytes {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(rename = "__PSB@BYTES")]
+        struct Inner {
+            #[serde(rename = "__PSB@BYTES")]
+            field: Vec<u8>,
+        }
+
+        Ok(Self(Inner::deserialize(de)?.field))
+    }
+}
+
+/// Serializes `items` as a [`PsbIntArray`] marker struct.
+fn serialize_int_array<S, T>(items: &[T], se: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+    T: Serialize,
+{
+    let mut st = se.serialize_struct(PsbIntArray::MARKER, 1)?;
+    st.serialize_field(PsbIntArray::MARKER, items)?;
+    st.end()
+}
+
 impl Serialize for PsbNumber {
     #[inline]
     fn serialize<S>(&self, se: S) -> Result<S::Ok, S::Error>
@@ -342,6 +467,7 @@ impl<'de> Visitor<'de> for KeyClassifier {
         Ok(match v {
             PsbResource::MARKER => KeyClass::Resource,
             PsbExtraResource::MARKER => KeyClass::ExtraResource,
+            PsbIntArray::MARKER => KeyClass::IntArray,
             PsbCompilerNumber::MARKER => KeyClass::CompilerNumber,
             PsbCompilerString::MARKER => KeyClass::CompilerString,
             PsbCompilerResource::MARKER => KeyClass::CompilerResource,
@@ -357,6 +483,7 @@ impl<'de> Visitor<'de> for KeyClassifier {
 enum KeyClass {
     Resource,
     ExtraResource,
+    IntArray,
     CompilerNumber,
     CompilerString,
     CompilerResource,
@@ -2,13 +2,13 @@
 
 pub mod de;
 pub mod number;
+pub mod resource;
 pub mod ser;
 
 mod impls;
 pub(crate) mod util;
 
-use std::collections::HashMap;
-
+use indexmap::IndexMap;
 use number::PsbNumber;
 use smol_str::SmolStr;
 
@@ -83,6 +83,9 @@ pub enum PsbValue {
 
     /// List of values
     List(Vec<PsbValue>),
+    /// Packed array of unsigned integers, see [`PsbIntArray`]
+    #[from(skip)]
+    IntArray(Vec<u64>),
 
     /// PSB intrinsic type: [`PsbCompilerNumber`]
     CompilerNumber,
@@ -99,8 +102,10 @@ pub enum PsbValue {
     /// PSB intrinsic type: [`PsbCompilerBinaryTree`]
     CompilerBinaryTree,
 
-    /// Map of values
-    Object(HashMap<SmolStr, PsbValue>),
+    /// Map of values, in file order when read and insertion order when built
+    ///
+    /// The serializer writes the entries sorted by name regardless of their order.
+    Object(IndexMap<SmolStr, PsbValue>),
 }
 
 macro_rules! define_special_type {
@@ -197,3 +202,46 @@ define_special_type!(
     /// PSB intrinsic marker
     pub PsbCompilerBinaryTree = "__PSB@CP@BTREE"
 );
+
+/// Packed array of unsigned integers.
+///
+/// Serialized as a PSB integer array, with every item encoded with the byte width of
+/// the largest one, instead of a list of integer values. Items of any unsigned
+/// integer type can be used. Deserializes from an integer array or a list.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct PsbIntArray<T = u64>(pub Vec<T>);
+
+impl PsbIntArray {
+    pub(crate) const MARKER: &str = "__PSB@INT@ARRAY";
+}
+
+impl<T> From<Vec<T>> for PsbIntArray<T> {
+    fn from(v: Vec<T>) -> Self {
+        Self(v)
+    }
+}
+
+/// Binary data serialized as a PSB resource.
+///
+/// The serializer collects the data into the [`Buffer`](ser::Buffer) as a resource
+/// and references it by index, and [`PsbWriter`] writes the collected resources
+/// before the ones added to it. Reading it from a [`PsbFile`] loads the data of the
+/// referenced resource or extra resource.
+///
+/// Collected resources are numbered from zero, so a value holding [`PsbBytes`] cannot
+/// also reference resources by index, e.g. with [`PsbResource`].
+///
+/// [`PsbWriter`]: crate::psb::write::PsbWriter
+/// [`PsbFile`]: crate::psb::read::PsbFile
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct PsbBytes(pub Vec<u8>);
+
+impl PsbBytes {
+    pub(crate) const MARKER: &str = "__PSB@BYTES";
+}
+
+impl From<Vec<u8>> for PsbBytes {
+    fn from(v: Vec<u8>) -> Self {
+        Self(v)
+    }
+}
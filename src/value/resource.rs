@@ -0,0 +1,124 @@
+//! Resource index compaction for PSB value trees.
+
+use std::collections::BTreeSet;
+
+use crate::value::PsbValue;
+
+/// Compacted numbering of the resources and extra resources referenced by a value
+/// tree.
+///
+/// Referenced resources keep their relative order. After rewriting a tree with
+/// [`apply`](ResourceRemap::apply), only the original resources listed by
+/// [`resources`](ResourceRemap::resources) and
+/// [`extra_resources`](ResourceRemap::extra_resources) need to be added to a
+/// [`PsbWriter`](crate::psb::write::PsbWriter), in that order.
+///
+/// ```
+/// use emote_psb::value::PsbValue;
+///
+/// let mut value = PsbValue::List(vec![PsbValue::Resource(4), PsbValue::Resource(1)]);
+/// let remap = value.compact_resources();
+/// assert_eq!(remap.resources(), [1, 4]);
+/// assert_eq!(
+///     value,
+///     PsbValue::List(vec![PsbValue::Resource(1), PsbValue::Resource(0)])
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResourceRemap {
+    resources: Vec<u32>,
+    extra: Vec<u32>,
+}
+
+impl ResourceRemap {
+    /// Collects the resource indices referenced by `value`.
+    pub fn new(value: &PsbValue) -> Self {
+        let mut resources = BTreeSet::new();
+        let mut extra = BTreeSet::new();
+        visit(value, &mut |value| match *value {
+            PsbValue::Resource(index) => {
+                resources.insert(index);
+            }
+            PsbValue::ExtraResource(index) => {
+                extra.insert(index);
+            }
+            _ => {}
+        });
+
+        Self {
+            resources: resources.into_iter().collect(),
+            extra: extra.into_iter().collect(),
+        }
+    }
+
+    /// Returns the original index of each referenced resource, by new index.
+    #[inline]
+    pub fn resources(&self) -> &[u32] {
+        &self.resources
+    }
+
+    /// Returns the original index of each referenced extra resource, by new index.
+    #[inline]
+    pub fn extra_resources(&self) -> &[u32] {
+        &self.extra
+    }
+
+    /// Returns the new index of the resource at `original`, or `None` if it is not
+    /// referenced.
+    pub fn resource(&self, original: u32) -> Option<u32> {
+        self.resources
+            .binary_search(&original)
+            .ok()
+            .map(|index| index as u32)
+    }
+
+    /// Returns the new index of the extra resource at `original`, or `None` if it is
+    /// not referenced.
+    pub fn extra_resource(&self, original: u32) -> Option<u32> {
+        self.extra
+            .binary_search(&original)
+            .ok()
+            .map(|index| index as u32)
+    }
+
+    /// Renumbers the resource references of `value`.
+    ///
+    /// References this remapping does not know are left unchanged.
+    pub fn apply(&self, value: &mut PsbValue) {
+        visit_mut(value, &mut |value| match value {
+            PsbValue::Resource(index) => {
+                *index = self.resource(*index).unwrap_or(*index);
+            }
+            PsbValue::ExtraResource(index) => {
+                *index = self.extra_resource(*index).unwrap_or(*index);
+            }
+            _ => {}
+        });
+    }
+}
+
+impl PsbValue {
+    /// Renumbers the resource references of this tree to consecutive indices, and
+    /// returns the applied [`ResourceRemap`].
+    pub fn compact_resources(&mut self) -> ResourceRemap {
+        let remap = ResourceRemap::new(self);
+        remap.apply(self);
+        remap
+    }
+}
+
+fn visit(value: &PsbValue, f: &mut impl FnMut(&PsbValue)) {
+    match value {
+        PsbValue::List(list) => list.iter().for_each(|value| visit(value, f)),
+        PsbValue::Object(map) => map.values().for_each(|value| visit(value, f)),
+        _ => f(value),
+    }
+}
+
+fn visit_mut(value: &mut PsbValue, f: &mut impl FnMut(&mut PsbValue)) {
+    match value {
+        PsbValue::List(list) => list.iter_mut().for_each(|value| visit_mut(value, f)),
+        PsbValue::Object(map) => map.values_mut().for_each(|value| visit_mut(value, f)),
+        _ => f(value),
+    }
+}
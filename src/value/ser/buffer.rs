@@ -1,9 +1,18 @@
-use std::io::{self, ErrorKind, Write};
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{self, ErrorKind, Write},
+    mem,
+};
 
 use indexmap::{IndexSet, set::Slice};
 use smol_str::SmolStr;
 
-use crate::value::ser::Error;
+use crate::value::{
+    PSB_TYPE_EXTRA_N, PSB_TYPE_LIST, PSB_TYPE_OBJECT, PSB_TYPE_RESOURCE_N, PSB_TYPE_STRING_N,
+    ser::{EncodedTree, Error},
+    util::{get_uint_n, write_uint_array},
+};
 
 /// Intermediate buffer that accumulates a serialized PSB value tree before it is
 /// written to an output stream.
@@ -12,6 +21,12 @@ use crate::value::ser::Error;
 /// [`PsbWriter::new_with_buffer`] to produce a complete PSB file. It can be
 /// [`cleared`](Buffer::clear) and reused across multiple serialize/write cycles.
 ///
+/// With [`set_dedup`](Buffer::set_dedup) enabled, identical encoded children of a
+/// list or object are written once and share the same offset.
+///
+/// Files sharing most of their names and strings can seed the tables with a
+/// [dictionary](Buffer::set_dictionary), which is kept when the buffer is cleared.
+///
 /// [`serialize`]: crate::value::ser::serialize
 /// [`PsbWriter::new_with_buffer`]: crate::psb::write::PsbWriter::new_with_buffer
 #[derive(Debug, Clone)]
@@ -22,6 +37,35 @@ pub struct Buffer {
     pub(crate) values: Vec<BufferValue>,
     pub(crate) objects: Vec<BufferObject>,
     pub(crate) indexes: Vec<usize>,
+    /// Name id of each object entry
+    pub(crate) keys: Vec<u32>,
+    /// Position of the written child of each list or object entry among the
+    /// written children
+    pub(crate) slots: Vec<usize>,
+    /// Hash of each encoded value, maintained when `dedup` is enabled
+    pub(crate) hashes: Vec<u64>,
+    pub(crate) dedup: bool,
+    /// One past the highest resource index referenced
+    pub(crate) resource_end: u64,
+    /// One past the highest extra resource index referenced
+    pub(crate) extra_end: u64,
+    /// Whether a resource is referenced by an explicit index
+    pub(crate) explicit_resources: bool,
+    /// Data of the resources collected from [`PsbBytes`](crate::value::PsbBytes)
+    pub(crate) resources: Vec<Vec<u8>>,
+    /// Sorted tables the name and string tables start from
+    dictionary: Option<Box<Dictionary>>,
+    prune_unused: bool,
+    /// Whether each name is referenced by the value tree, by final id
+    used_names: Vec<bool>,
+    /// Whether each string is referenced by the value tree, by final id
+    used_strings: Vec<bool>,
+}
+
+#[derive(Debug, Clone)]
+struct Dictionary {
+    names: IndexSet<SmolStr>,
+    strings: IndexSet<SmolStr>,
 }
 
 impl Buffer {
@@ -34,9 +78,107 @@ impl Buffer {
             values: vec![],
             objects: vec![],
             indexes: vec![],
+            keys: vec![],
+            slots: vec![],
+            hashes: vec![],
+            dedup: false,
+            resource_end: 0,
+            extra_end: 0,
+            explicit_resources: false,
+            resources: vec![],
+            dictionary: None,
+            prune_unused: false,
+            used_names: vec![],
+            used_strings: vec![],
         }
     }
 
+    /// Enables or disables deduplication of identical subtrees within a list or object.
+    ///
+    /// Takes effect for values serialized after this call.
+    #[inline]
+    pub fn set_dedup(&mut self, dedup: bool) {
+        self.dedup = dedup;
+    }
+
+    /// Returns `true` if subtree deduplication is enabled.
+    #[inline]
+    pub const fn dedup(&self) -> bool {
+        self.dedup
+    }
+
+    /// Seeds the name and string tables with `names` and `strings`, and clears the
+    /// buffer.
+    ///
+    /// The dictionary is sorted once and restored on every [`clear`](Buffer::clear),
+    /// so that only the entries missing from it are added and sorted by each
+    /// serialization. Entries the value tree does not use are still written, unless
+    /// [`set_prune_unused`](Buffer::set_prune_unused) is enabled.
+    pub fn set_dictionary<N, S>(
+        &mut self,
+        names: impl IntoIterator<Item = N>,
+        strings: impl IntoIterator<Item = S>,
+    ) where
+        N: Into<SmolStr>,
+        S: Into<SmolStr>,
+    {
+        fn sorted<T: Into<SmolStr>>(entries: impl IntoIterator<Item = T>) -> IndexSet<SmolStr> {
+            let mut entries = entries.into_iter().map(Into::into).collect::<Vec<_>>();
+            entries.sort_unstable();
+            entries.dedup();
+            entries.into_iter().collect()
+        }
+
+        self.dictionary = Some(Box::new(Dictionary {
+            names: sorted(names),
+            strings: sorted(strings),
+        }));
+        self.clear();
+    }
+
+    /// Enables or disables removing the names and strings not used by the value tree
+    /// from the tables, such as unused [dictionary](Buffer::set_dictionary) entries.
+    ///
+    /// Takes effect for values serialized after this call.
+    #[inline]
+    pub fn set_prune_unused(&mut self, prune_unused: bool) {
+        self.prune_unused = prune_unused;
+    }
+
+    /// Returns `true` if unused names and strings are removed from the tables.
+    #[inline]
+    pub const fn prune_unused(&self) -> bool {
+        self.prune_unused
+    }
+
+    /// Returns the names of the table referenced by the serialized value tree, in
+    /// table order.
+    pub fn used_names(&self) -> impl Iterator<Item = &SmolStr> {
+        self.names
+            .iter()
+            .zip(&self.used_names)
+            .filter_map(|(name, &used)| used.then_some(name))
+    }
+
+    /// Returns the strings of the table referenced by the serialized value tree, in
+    /// table order.
+    pub fn used_strings(&self) -> impl Iterator<Item = &SmolStr> {
+        self.strings
+            .iter()
+            .zip(&self.used_strings)
+            .filter_map(|(string, &used)| used.then_some(string))
+    }
+
+    /// Returns the data of the resources collected from
+    /// [`PsbBytes`](crate::value::PsbBytes) values, by resource index.
+    ///
+    /// They are moved to the [`PsbWriter`] the buffer is written with.
+    ///
+    /// [`PsbWriter`]: crate::psb::write::PsbWriter
+    pub fn resources(&self) -> &[Vec<u8>] {
+        &self.resources
+    }
+
     /// Returns a slice of all collected object-key names in their serialized (sorted) order.
     pub fn names(&self) -> &Slice<SmolStr> {
         self.names.as_slice()
@@ -48,12 +190,33 @@ impl Buffer {
     }
 
     /// Clear buffer for reuse
+    ///
+    /// The name and string tables are reset to the
+    /// [dictionary](Buffer::set_dictionary), if any.
     pub fn clear(&mut self) {
         self.bytes.clear();
         self.values.clear();
         self.objects.clear();
-        self.names.clear();
-        self.strings.clear();
+        self.indexes.clear();
+        self.keys.clear();
+        self.slots.clear();
+        self.hashes.clear();
+        match &self.dictionary {
+            Some(dictionary) => {
+                self.names.clone_from(&dictionary.names);
+                self.strings.clone_from(&dictionary.strings);
+            }
+            None => {
+                self.names.clear();
+                self.strings.clear();
+            }
+        }
+        self.used_names.clear();
+        self.used_strings.clear();
+        self.resource_end = 0;
+        self.extra_end = 0;
+        self.explicit_resources = false;
+        self.resources.clear();
     }
 
     /// Writes the serialized PSB value tree to `stream`, starting from the root value.
@@ -67,13 +230,78 @@ impl Buffer {
         Ok(())
     }
 
+    /// Returns the byte size of the serialized PSB value tree, as written by
+    /// [`write`](Buffer::write).
+    #[inline]
+    pub fn encoded_len(&self) -> u64 {
+        self.values
+            .first()
+            .map_or(0, |value| value.size(self) as u64)
+    }
+
+    /// Collects resource references in write order, with their offset from the
+    /// start of the written tree.
+    pub(crate) fn resource_refs(&self) -> Vec<ResourceRef> {
+        let mut refs = vec![];
+        self.resource_refs_inner(0, &mut 0, &mut refs);
+        refs
+    }
+
+    fn resource_refs_inner(
+        &self,
+        value_index: usize,
+        offset: &mut usize,
+        refs: &mut Vec<ResourceRef>,
+    ) {
+        let Some(&current) = self.values.get(value_index) else {
+            return;
+        };
+
+        match current {
+            BufferValue::Invalid | BufferValue::Spooled { .. } => {}
+            BufferValue::Value { data_start, size } => {
+                let extra = match self.bytes[data_start] {
+                    ty if (PSB_TYPE_RESOURCE_N + 1..=PSB_TYPE_RESOURCE_N + 4).contains(&ty) => {
+                        false
+                    }
+                    ty if (PSB_TYPE_EXTRA_N + 1..=PSB_TYPE_EXTRA_N + 4).contains(&ty) => true,
+                    _ => {
+                        *offset += size as usize;
+                        return;
+                    }
+                };
+
+                let width = size as usize - 1;
+                let mut index = [0; 4];
+                index[..width].copy_from_slice(&self.bytes[data_start + 1..][..width]);
+                refs.push(ResourceRef {
+                    offset: *offset + 1,
+                    width: width as u8,
+                    index: u32::from_le_bytes(index),
+                    extra,
+                });
+                *offset += size as usize;
+            }
+            BufferValue::Object { index } => {
+                let object = self.objects[index];
+                *offset += object.header_end - object.header_start;
+
+                for i in 0..object.len {
+                    self.resource_refs_inner(self.indexes[object.index_start + i], offset, refs);
+                }
+            }
+        }
+    }
+
     fn write_inner(&self, value_index: usize, stream: &mut impl Write) -> io::Result<()> {
         let Some(&current) = self.values.get(value_index) else {
             return Ok(());
         };
 
         match current {
-            BufferValue::Invalid => Err(ErrorKind::InvalidData.into()),
+            BufferValue::Invalid | BufferValue::Spooled { .. } => {
+                Err(ErrorKind::InvalidData.into())
+            }
             BufferValue::Value { data_start, size } => {
                 stream.write_all(&self.bytes[data_start..][..size as usize])?;
                 Ok(())
@@ -102,7 +330,473 @@ impl Buffer {
             data_start,
             size: (self.bytes.len() - data_start) as u32,
         });
+        if self.dedup {
+            let mut hasher = DefaultHasher::new();
+            self.bytes[data_start..].hash(&mut hasher);
+            self.hashes.push(hasher.finish());
+        }
+
+        Ok(())
+    }
+
+    /// Collects `data` as the next resource and writes a reference to it.
+    ///
+    /// Collected resources are numbered from zero, so they cannot be mixed with
+    /// explicit resource indices.
+    pub(crate) fn push_resource(&mut self, data: &[u8]) -> Result<(), Error> {
+        if self.explicit_resources {
+            return Err(Error::MixedResources);
+        }
+
+        let index = self.resources.len();
+        let n = get_uint_n(index as _);
+        if n > 4 {
+            return Err(Error::IndexOverflow);
+        }
+
+        self.write_value(|bytes| {
+            bytes.push(PSB_TYPE_RESOURCE_N + n);
+            bytes.extend_from_slice(&index.to_le_bytes()[..n as usize]);
+            Ok(())
+        })?;
+        self.resources.push(data.to_vec());
+        self.resource_end = self.resource_end.max(index as u64 + 1);
+        Ok(())
+    }
+
+    /// Returns the provisional id of `name`, adding it to the name table if missing.
+    pub(crate) fn insert_name(&mut self, name: &str) -> Result<u32, Error> {
+        let index = match self.names.get_index_of(name) {
+            Some(index) => index,
+            None => self.names.insert_full(SmolStr::new(name)).0,
+        };
+        index.try_into().map_err(|_| Error::IndexOverflow)
+    }
+
+    /// Reserves a slot for a list or object value and returns its index.
+    pub(crate) fn push_placeholder(&mut self) -> usize {
+        let index = self.values.len();
+        self.values.push(BufferValue::Invalid);
+        if self.dedup {
+            self.hashes.push(0);
+        }
+        index
+    }
+
+    /// Lays out the children of a list or object in write order, pushing the
+    /// written children to `indexes` and the position of the written child of each
+    /// entry to `slots`.
+    pub(crate) fn push_children(
+        &mut self,
+        children: impl IntoIterator<Item = usize>,
+        seen: &mut HashMap<u64, (usize, usize)>,
+    ) {
+        let index_start = self.indexes.len();
+        for value_index in children {
+            if self.dedup {
+                let hash = self.hashes[value_index];
+                match seen.get(&hash) {
+                    Some(&(prev, prev_slot)) if self.encoded_eq(prev, value_index) => {
+                        self.slots.push(prev_slot);
+                        continue;
+                    }
+                    Some(_) => {}
+                    None => {
+                        seen.insert(hash, (value_index, self.indexes.len() - index_start));
+                    }
+                }
+            }
+
+            self.slots.push(self.indexes.len() - index_start);
+            self.indexes.push(value_index);
+        }
+        seen.clear();
+    }
+
+    /// Finishes the list or object at `value_index` with entries `slots[slot_start..]`,
+    /// written children `indexes[index_start..]`, and for objects names
+    /// `keys[key_start..]`.
+    ///
+    /// The header is encoded by [`resolve`](Buffer::resolve).
+    pub(crate) fn finish_object(
+        &mut self,
+        value_index: usize,
+        key_start: Option<usize>,
+        index_start: usize,
+        slot_start: usize,
+    ) {
+        let object = BufferObject {
+            len: self.indexes.len() - index_start,
+            entries: self.slots.len() - slot_start,
+            key_start,
+            slot_start,
+            header_start: 0,
+            header_end: 0,
+            index_start,
+            size: 0,
+        };
+        if self.dedup {
+            self.hashes[value_index] = self.object_hash(object);
+        }
+
+        let index = self.objects.len();
+        self.objects.push(object);
+        self.values[value_index] = BufferValue::Object { index };
+    }
+
+    fn object_hash(&self, object: BufferObject) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        if let Some(key_start) = object.key_start {
+            self.keys[key_start..][..object.entries].hash(&mut hasher);
+        } else {
+            hasher.write_u8(0);
+        }
+        self.slots[object.slot_start..][..object.entries].hash(&mut hasher);
+        for &child in &self.indexes[object.index_start..][..object.len] {
+            hasher.write_u64(self.hashes[child]);
+        }
+        hasher.finish()
+    }
+
+    /// Appends the values serialized in `other`, remapping its names and strings to
+    /// the tables of this buffer. Returns the index of the first appended value.
+    ///
+    /// Both buffers must not be resolved yet.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn append(&mut self, other: Buffer) -> Result<usize, Error> {
+        let names = other
+            .names
+            .into_iter()
+            .map(|name| self.insert_name(&name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let strings = other
+            .strings
+            .into_iter()
+            .map(|string| {
+                let index = self.strings.insert_full(string).0;
+                u32::try_from(index).map_err(|_| Error::IndexOverflow)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let value_start = self.values.len();
+        let object_start = self.objects.len();
+        let index_start = self.indexes.len();
+        let key_start = self.keys.len();
+        let slot_start = self.slots.len();
+
+        self.keys
+            .extend(other.keys.iter().map(|&key| names[key as usize]));
+        self.slots.extend_from_slice(&other.slots);
+        self.indexes
+            .extend(other.indexes.iter().map(|&index| index + value_start));
+        self.objects
+            .extend(other.objects.iter().map(|&object| BufferObject {
+                key_start: object.key_start.map(|start| start + key_start),
+                slot_start: object.slot_start + slot_start,
+                index_start: object.index_start + index_start,
+                ..object
+            }));
+
+        for &value in &other.values {
+            self.values.push(match value {
+                BufferValue::Value { data_start, size } => {
+                    let value = &other.bytes[data_start..][..size as usize];
+                    let data_start = self.bytes.len();
+                    match string_id(value) {
+                        Some(id) => {
+                            let id = strings[id as usize];
+                            let n = get_uint_n(id as _);
+                            self.bytes.push(PSB_TYPE_STRING_N + n);
+                            self.bytes
+                                .extend_from_slice(&id.to_le_bytes()[..n as usize]);
+                        }
+                        None => self.bytes.extend_from_slice(value),
+                    }
+
+                    BufferValue::Value {
+                        data_start,
+                        size: (self.bytes.len() - data_start) as u32,
+                    }
+                }
+                BufferValue::Object { index } => BufferValue::Object {
+                    index: index + object_start,
+                },
+                value => value,
+            });
+        }
+
+        if self.dedup {
+            // Name and string ids are part of the hashes
+            self.hashes.resize(self.values.len(), 0);
+            for value_index in (value_start..self.values.len()).rev() {
+                self.hashes[value_index] = match self.values[value_index] {
+                    BufferValue::Value { data_start, size } => {
+                        let mut hasher = DefaultHasher::new();
+                        self.bytes[data_start..][..size as usize].hash(&mut hasher);
+                        hasher.finish()
+                    }
+                    BufferValue::Object { index } => self.object_hash(self.objects[index]),
+                    _ => 0,
+                };
+            }
+        }
+
+        // Resources are only collected from `PsbBytes`, which `PsbValue` never holds.
+        debug_assert!(other.resources.is_empty());
+        if other.explicit_resources && !self.resources.is_empty() {
+            return Err(Error::MixedResources);
+        }
+        self.explicit_resources |= other.explicit_resources;
+        self.resource_end = self.resource_end.max(other.resource_end);
+        self.extra_end = self.extra_end.max(other.extra_end);
+        Ok(value_start)
+    }
+
+    /// Sorts the name and string tables, and encodes the value tree with the final
+    /// name and string ids.
+    ///
+    /// Until then, names and strings are encoded with their insertion index.
+    pub(crate) fn resolve(&mut self) -> Result<(), Error> {
+        let mut used_names = vec![false; self.names.len()];
+        for &key in &self.keys {
+            used_names[key as usize] = true;
+        }
+        let mut used_strings = vec![false; self.strings.len()];
+        for &value in &self.values {
+            if let BufferValue::Value { data_start, size } = value
+                && let Some(id) = string_id(&self.bytes[data_start..][..size as usize])
+            {
+                used_strings[id as usize] = true;
+            }
+        }
+
+        let (names, strings) = self.sort_tables(Some((&used_names, &used_strings)));
+        for key in &mut self.keys {
+            *key = names[*key as usize];
+        }
+
+        let data = mem::take(&mut self.bytes);
+        let mut child_offsets = vec![];
+        let mut offsets = vec![];
+        // Children always follow their parent, so they are encoded first.
+        for value_index in (0..self.values.len()).rev() {
+            match self.values[value_index] {
+                BufferValue::Invalid | BufferValue::Spooled { .. } => {}
+
+                BufferValue::Value { data_start, size } => {
+                    let value = &data[data_start..][..size as usize];
+                    let start = self.bytes.len();
+                    match string_id(value) {
+                        Some(id) => {
+                            let id = strings[id as usize];
+                            let n = get_uint_n(id as _);
+                            self.bytes.push(PSB_TYPE_STRING_N + n);
+                            self.bytes
+                                .extend_from_slice(&id.to_le_bytes()[..n as usize]);
+                        }
+                        None => self.bytes.extend_from_slice(value),
+                    }
+
+                    self.values[value_index] = BufferValue::Value {
+                        data_start: start,
+                        size: (self.bytes.len() - start) as u32,
+                    };
+                }
+
+                BufferValue::Object { index } => {
+                    let object = self.objects[index];
+                    let mut offset = 0;
+                    for &child in &self.indexes[object.index_start..][..object.len] {
+                        child_offsets.push(offset as u64);
+                        offset += self.values[child].size(self);
+                    }
+                    offsets.extend(
+                        self.slots[object.slot_start..][..object.entries]
+                            .iter()
+                            .map(|&slot| child_offsets[slot]),
+                    );
+
+                    let header_start = self.bytes.len();
+                    if let Some(key_start) = object.key_start {
+                        self.bytes.push(PSB_TYPE_OBJECT);
+                        write_uint_array(
+                            &mut self.bytes,
+                            &self.keys[key_start..][..object.entries],
+                        )?;
+                    } else {
+                        self.bytes.push(PSB_TYPE_LIST);
+                    }
+                    write_uint_array(&mut self.bytes, &offsets)?;
+                    child_offsets.clear();
+                    offsets.clear();
+
+                    let header_end = self.bytes.len();
+                    self.objects[index] = BufferObject {
+                        header_start,
+                        header_end,
+                        size: header_end - header_start + offset,
+                        ..object
+                    };
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sorts the name and string tables, returning the new id of each name and
+    /// string by its insertion index.
+    ///
+    /// With `used` flags of the names and strings, unused entries are removed if
+    /// [`prune_unused`](Buffer::prune_unused) is enabled. Otherwise every entry is
+    /// considered used.
+    pub(crate) fn sort_tables(&mut self, used: Option<(&[bool], &[bool])>) -> (Vec<u32>, Vec<u32>) {
+        let (names_len, strings_len) = self.dictionary.as_ref().map_or((0, 0), |dictionary| {
+            (dictionary.names.len(), dictionary.strings.len())
+        });
+        let (used_names, used_strings) = used.unzip();
+        let prune = self.prune_unused;
 
+        let names = sort_table(
+            &mut self.names,
+            names_len,
+            used_names,
+            prune,
+            &mut self.used_names,
+        );
+        let strings = sort_table(
+            &mut self.strings,
+            strings_len,
+            used_strings,
+            prune,
+            &mut self.used_strings,
+        );
+        (names, strings)
+    }
+
+    /// Returns `true` if the values at `a` and `b` have identical encodings.
+    pub(crate) fn encoded_eq(&self, a: usize, b: usize) -> bool {
+        match (self.values[a], self.values[b]) {
+            (
+                BufferValue::Value {
+                    data_start: a_start,
+                    size: a_size,
+                },
+                BufferValue::Value {
+                    data_start: b_start,
+                    size: b_size,
+                },
+            ) => {
+                self.bytes[a_start..][..a_size as usize] == self.bytes[b_start..][..b_size as usize]
+            }
+
+            (BufferValue::Object { index: a }, BufferValue::Object { index: b }) => {
+                let (a, b) = (self.objects[a], self.objects[b]);
+                let keys = |object: BufferObject| {
+                    object
+                        .key_start
+                        .map(|start| &self.keys[start..][..object.entries])
+                };
+                let slots =
+                    |object: BufferObject| &self.slots[object.slot_start..][..object.entries];
+
+                a.len == b.len
+                    && keys(a) == keys(b)
+                    && slots(a) == slots(b)
+                    && (0..a.len).all(|i| {
+                        self.encoded_eq(
+                            self.indexes[a.index_start + i],
+                            self.indexes[b.index_start + i],
+                        )
+                    })
+            }
+
+            _ => false,
+        }
+    }
+}
+
+/// Returns the id encoded by a string value.
+fn string_id(value: &[u8]) -> Option<u32> {
+    if !(PSB_TYPE_STRING_N + 1..=PSB_TYPE_STRING_N + 4).contains(&value[0]) {
+        return None;
+    }
+
+    let mut id = [0; 4];
+    id[..value.len() - 1].copy_from_slice(&value[1..]);
+    Some(u32::from_le_bytes(id))
+}
+
+/// Sorts `table`, whose first `sorted_len` entries are already sorted, returning the
+/// new index of each entry by its previous index.
+///
+/// Entries not marked in `used` are removed if `prune` is set, and their index is
+/// `u32::MAX`. Whether each remaining entry is used is written to `used_out`.
+fn sort_table(
+    table: &mut IndexSet<SmolStr>,
+    sorted_len: usize,
+    used: Option<&[bool]>,
+    prune: bool,
+    used_out: &mut Vec<bool>,
+) -> Vec<u32> {
+    let is_used = |index: usize| used.is_none_or(|used| used[index]);
+    let keep = |&index: &usize| !prune || is_used(index);
+
+    let mut added = (sorted_len..table.len()).filter(keep).collect::<Vec<_>>();
+    added.sort_unstable_by(|&a, &b| table[a].cmp(&table[b]));
+
+    let mut order = Vec::with_capacity(table.len());
+    let mut sorted = (0..sorted_len).filter(keep).peekable();
+    let mut added = added.into_iter().peekable();
+    loop {
+        let next = match (sorted.peek(), added.peek()) {
+            (Some(&a), Some(&b)) if table[a] < table[b] => sorted.next(),
+            (Some(_), Some(_)) | (None, Some(_)) => added.next(),
+            (Some(_), None) => sorted.next(),
+            (None, None) => break,
+        };
+        order.extend(next);
+    }
+
+    let mut ids = vec![u32::MAX; table.len()];
+    for (id, &index) in order.iter().enumerate() {
+        ids[index] = id as u32;
+    }
+    used_out.clear();
+    used_out.extend(order.iter().map(|&index| is_used(index)));
+    // Already sorted when every entry is found in the dictionary
+    if order.len() != table.len() || order.iter().enumerate().any(|(id, &index)| id != index) {
+        *table = order.iter().map(|&index| table[index].clone()).collect();
+    }
+    ids
+}
+
+impl EncodedTree for Buffer {
+    fn names(&self) -> &Slice<SmolStr> {
+        self.names()
+    }
+
+    fn strings(&self) -> &Slice<SmolStr> {
+        self.strings()
+    }
+
+    fn resource_end(&self) -> (u64, u64) {
+        (self.resource_end, self.extra_end)
+    }
+
+    fn take_resources(&mut self) -> Vec<Vec<u8>> {
+        mem::take(&mut self.resources)
+    }
+
+    fn write_tree(
+        &mut self,
+        mut stream: &mut dyn Write,
+        refs: Option<&mut Vec<ResourceRef>>,
+    ) -> io::Result<()> {
+        self.write(&mut stream)?;
+        if let Some(refs) = refs {
+            *refs = self.resource_refs();
+        }
         Ok(())
     }
 }
@@ -116,19 +810,25 @@ impl Default for Buffer {
 /// Temporary buffers for list, map serialization
 pub(crate) struct SerializerBuffer {
     pub keys: Vec<u32>,
-    pub offsets: Vec<u64>,
     pub map_indexes: Vec<usize>,
     pub permutations: Vec<usize>,
+    /// Hash, value index and position of written children, used for deduplication
+    pub seen: HashMap<u64, (usize, usize)>,
+    pub offsets: Vec<u64>,
+    /// Record of the list or object being spooled
+    pub record: Vec<u8>,
 }
 
 impl SerializerBuffer {
     #[inline]
-    pub const fn new() -> Self {
+    pub fn new() -> Self {
         Self {
             keys: vec![],
-            offsets: vec![],
             map_indexes: vec![],
             permutations: vec![],
+            seen: HashMap::new(),
+            offsets: vec![],
+            record: vec![],
         }
     }
 }
@@ -142,6 +842,9 @@ pub enum BufferValue {
     Value { data_start: usize, size: u32 },
     /// A composite value (list or object) whose children are tracked in the object table.
     Object { index: usize },
+    /// A composite value written to a spool as a record at `position`, encoding
+    /// `size` bytes.
+    Spooled { position: u64, size: usize },
 }
 
 impl BufferValue {
@@ -149,25 +852,40 @@ impl BufferValue {
         match self {
             BufferValue::Invalid => 0,
             BufferValue::Value { size, .. } => size as _,
-            BufferValue::Object { index } => {
-                let obj = buf.objects[index];
-                obj.header_end - obj.data_start
-            }
+            BufferValue::Object { index } => buf.objects[index].size,
+            BufferValue::Spooled { size, .. } => size,
         }
     }
 }
 
+/// A resource or extra resource value written by a [`Buffer`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ResourceRef {
+    /// Offset of the encoded index from the start of the written tree
+    pub offset: usize,
+    /// Byte width of the encoded index
+    pub width: u8,
+    pub index: u32,
+    pub extra: bool,
+}
+
 /// Metadata for a list or object node stored in a [`Buffer`]'s object table.
 #[derive(Debug, Clone, Copy)]
 pub struct BufferObject {
-    /// Number of child values.
+    /// Number of written child values. Deduplicated children are not counted.
     pub len: usize,
-    /// Byte offset in `Buffer::bytes` where this node's data begins.
-    pub data_start: usize,
+    /// Number of entries, deduplicated children included.
+    pub entries: usize,
+    /// Starting index in `Buffer::keys` of the entry names, `None` for lists.
+    pub key_start: Option<usize>,
+    /// Starting index in `Buffer::slots` of the entries.
+    pub slot_start: usize,
     /// Byte offset of the serialized header (type tag + offset/key arrays).
     pub header_start: usize,
     /// Byte offset immediately after the header (first byte of child data).
     pub header_end: usize,
     /// Starting index in `Buffer::indexes` for this node's children.
     pub index_start: usize,
+    /// Total encoded size of this node, header included.
+    pub size: usize,
 }
@@ -22,6 +22,13 @@ pub enum Error {
     #[error("invalid psb specific value. marker: {0}")]
     InvalidValue(&'static str),
 
+    /// The value tree holds both [`PsbBytes`] collected as resources and explicit
+    /// resource indices, which the collected resources would shadow.
+    ///
+    /// [`PsbBytes`]: crate::value::PsbBytes
+    #[error("psb bytes cannot be mixed with explicit resource indices")]
+    MixedResources,
+
     /// A custom error message produced by serde.
     #[error("{0}")]
     Message(String),
@@ -1,20 +1,19 @@
-use byteorder::WriteBytesExt;
-use serde::ser::{Impossible, SerializeMap, SerializeStruct, SerializeStructVariant};
-
-use crate::value::{
-    PSB_TYPE_OBJECT,
-    ser::{
-        Error, Serializer, State,
-        buffer::{BufferObject, BufferValue},
-        special::SpecialValueSerializer,
-        value::{ref_type::RefTypeSerializer, unit::UnitTypeSerializer},
+use serde::ser::{Impossible, SerializeMap, SerializeStruct};
+
+use crate::value::ser::{
+    Error, Serializer, State,
+    special::SpecialValueSerializer,
+    value::{
+        bytes::BytesSerializer, int_array::IntArraySerializer, ref_type::RefTypeSerializer,
+        unit::UnitTypeSerializer,
     },
-    util::write_uint_array,
 };
 
 pub enum StructSerializer<'a> {
     Map(MapSerializer<'a>),
     RefTy(SpecialValueSerializer<RefTypeSerializer<'a>>),
+    IntArray(SpecialValueSerializer<IntArraySerializer<'a>>),
+    Bytes(SpecialValueSerializer<BytesSerializer<'a>>),
     UnitTy(SpecialValueSerializer<UnitTypeSerializer<'a>>),
 }
 
@@ -29,6 +28,8 @@ impl<'a> SerializeStruct for StructSerializer<'a> {
         match self {
             StructSerializer::Map(se) => SerializeStruct::serialize_field(se, key, value),
             StructSerializer::RefTy(se) => se.serialize_field(key, value),
+            StructSerializer::IntArray(se) => se.serialize_field(key, value),
+            StructSerializer::Bytes(se) => se.serialize_field(key, value),
             StructSerializer::UnitTy(se) => se.serialize_field(key, value),
         }
     }
@@ -37,6 +38,8 @@ impl<'a> SerializeStruct for StructSerializer<'a> {
         match self {
             StructSerializer::Map(se) => SerializeMap::end(se),
             StructSerializer::RefTy(se) => se.end(),
+            StructSerializer::IntArray(se) => se.end(),
+            StructSerializer::Bytes(se) => se.end(),
             StructSerializer::UnitTy(se) => se.end(),
         }
     }
@@ -45,7 +48,6 @@ impl<'a> SerializeStruct for StructSerializer<'a> {
 pub struct MapSerializer<'a> {
     len: usize,
     map_index: usize,
-    data_start: usize,
     key_start: usize,
     map_index_start: usize,
     state: State<'a>,
@@ -59,20 +61,28 @@ impl<'a> MapSerializer<'a> {
             state.ser.map_indexes.reserve(len);
         }
 
-        let map_index = state.buf.values.len();
-        state.buf.values.push(BufferValue::Invalid);
-        let data_start = state.buf.bytes.len();
+        let map_index = state.buf.push_placeholder();
         let key_start = state.ser.keys.len();
         let map_index_start = state.ser.map_indexes.len();
         Self {
             len: 0,
             map_index,
-            data_start,
             key_start,
             map_index_start,
             state,
         }
     }
+
+    /// Finishes the object, returning the state it was serialized with.
+    pub fn finish(mut self) -> Result<State<'a>, Error> {
+        self.state.end_map(
+            self.map_index,
+            self.key_start,
+            self.map_index_start,
+            self.len,
+        )?;
+        Ok(self.state)
+    }
 }
 
 impl<'a> SerializeMap for MapSerializer<'a> {
@@ -99,59 +109,7 @@ impl<'a> SerializeMap for MapSerializer<'a> {
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        debug_assert_eq!(self.state.ser.keys.len() - self.key_start, self.len);
-        debug_assert_eq!(
-            self.state.ser.map_indexes.len() - self.map_index_start,
-            self.len
-        );
-
-        self.state.ser.permutations.reserve(self.len);
-        for i in 0..self.len {
-            self.state.ser.permutations.push(i);
-        }
-        self.state.ser.permutations.sort_unstable_by(|a, b| {
-            self.state.ser.keys[self.key_start + a].cmp(&self.state.ser.keys[self.key_start + b])
-        });
-        self.state.ser.keys[self.key_start..].sort_unstable();
-
-        self.state.ser.offsets.reserve(self.len);
-        self.state.buf.indexes.reserve(self.len);
-        let index_start = self.state.buf.indexes.len();
-        let mut offset = 0;
-        for src_i in 0..self.len {
-            let dest_i = self.state.ser.permutations[src_i];
-            let value_index = self.state.ser.map_indexes[self.map_index_start + dest_i];
-            self.state.ser.offsets.push(offset);
-            self.state.buf.indexes.push(value_index);
-
-            offset += self.state.buf.values[value_index].size(self.state.buf) as u64;
-        }
-        self.state.ser.permutations.clear();
-
-        let header_start = self.state.buf.bytes.len();
-        self.state.buf.bytes.write_u8(PSB_TYPE_OBJECT)?;
-        write_uint_array(
-            &mut self.state.buf.bytes,
-            &self.state.ser.keys[self.key_start..],
-        )?;
-        write_uint_array(&mut self.state.buf.bytes, &self.state.ser.offsets)?;
-        let header_end = self.state.buf.bytes.len();
-
-        self.state.ser.keys.drain(self.key_start..);
-        self.state.ser.map_indexes.drain(self.map_index_start..);
-        self.state.ser.offsets.clear();
-
-        let index = self.state.buf.objects.len();
-        self.state.buf.objects.push(BufferObject {
-            len: self.len,
-            data_start: self.data_start,
-            header_start,
-            header_end,
-            index_start,
-        });
-
-        self.state.buf.values[self.map_index] = BufferValue::Object { index };
-        Ok(())
+        self.finish().map(drop)
     }
 }
 
@@ -171,19 +129,52 @@ impl SerializeStruct for MapSerializer<'_> {
     }
 }
 
-impl SerializeStructVariant for MapSerializer<'_> {
-    type Ok = <Self as SerializeMap>::Ok;
-    type Error = <Self as SerializeMap>::Error;
+impl State<'_> {
+    /// Finishes the object at `map_index` with the names from `key_start` and
+    /// values from `map_index_start`.
+    pub(super) fn end_map(
+        &mut self,
+        map_index: usize,
+        key_start: usize,
+        map_index_start: usize,
+        len: usize,
+    ) -> Result<(), Error> {
+        debug_assert_eq!(self.ser.keys.len() - key_start, len);
+        debug_assert_eq!(self.ser.map_indexes.len() - map_index_start, len);
+
+        // Entries are ordered by name, as the final name ids are.
+        let names = &self.buf.names;
+        let keys = &self.ser.keys[key_start..];
+        self.ser.permutations.reserve(len);
+        for i in 0..len {
+            self.ser.permutations.push(i);
+        }
+        self.ser
+            .permutations
+            .sort_unstable_by(|&a, &b| names[keys[a] as usize].cmp(&names[keys[b] as usize]));
 
-    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error>
-    where
-        T: ?Sized + serde::Serialize,
-    {
-        SerializeMap::serialize_entry(self, key, value)
-    }
+        let buf_key_start = self.buf.keys.len();
+        self.buf
+            .keys
+            .extend(self.ser.permutations.iter().map(|&i| keys[i]));
+
+        self.buf.indexes.reserve(len);
+        let index_start = self.buf.indexes.len();
+        let slot_start = self.buf.slots.len();
+        let map_indexes = &self.ser.map_indexes[map_index_start..];
+        self.buf.push_children(
+            self.ser
+                .permutations
+                .iter()
+                .map(|&dest_i| map_indexes[dest_i]),
+            &mut self.ser.seen,
+        );
+        self.ser.permutations.clear();
 
-    fn end(self) -> Result<Self::Ok, Self::Error> {
-        SerializeMap::end(self)
+        self.ser.keys.drain(key_start..);
+        self.ser.map_indexes.drain(map_index_start..);
+
+        self.finish_object(map_index, Some(buf_key_start), index_start, slot_start)
     }
 }
 
@@ -202,11 +193,8 @@ impl<'a> serde::Serializer for NameSerializer<'a> {
     type SerializeStructVariant = Impossible<Self::Ok, Self::Error>;
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
-        let index = self.0.buf.names.get_index_of(v).ok_or(Error::InvalidKey)?;
-        self.0
-            .ser
-            .keys
-            .push(index.try_into().map_err(|_| Error::IndexOverflow)?);
+        let id = self.0.buf.insert_name(v)?;
+        self.0.ser.keys.push(id);
         Ok(())
     }
 
@@ -285,9 +273,9 @@ impl<'a> serde::Serializer for NameSerializer<'a> {
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
+        variant: &'static str,
     ) -> Result<Self::Ok, Self::Error> {
-        Err(Error::InvalidKey)
+        self.serialize_str(variant)
     }
 
     fn serialize_newtype_struct<T>(
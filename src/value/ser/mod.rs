@@ -3,17 +3,27 @@
 mod buffer;
 mod error;
 mod map;
+#[cfg(feature = "rayon")]
+pub(crate) mod par;
 mod seq;
 mod special;
-mod string;
+mod spool;
 mod value;
+mod variant;
 
 pub use buffer::Buffer;
+pub(crate) use buffer::ResourceRef;
 pub use error::Error;
+pub use spool::{SpooledTree, serialize_spooled};
 
-use std::io::Write;
+use std::io::{self, Write};
 
-use serde::{Serialize, ser::SerializeSeq};
+use indexmap::set::Slice;
+use serde::{
+    Serialize,
+    ser::{SerializeMap, SerializeSeq},
+};
+use smol_str::SmolStr;
 
 use byteorder::{LittleEndian, WriteBytesExt};
 
@@ -21,44 +31,87 @@ use crate::value::{
     PSB_COMPILER_ARRAY, PSB_COMPILER_BINARY_TREE, PSB_COMPILER_BOOL, PSB_COMPILER_DECIMAL,
     PSB_COMPILER_INTEGER, PSB_COMPILER_RESOURCE, PSB_COMPILER_STRING, PSB_TYPE_DOUBLE,
     PSB_TYPE_EXTRA_N, PSB_TYPE_FALSE, PSB_TYPE_FLOAT, PSB_TYPE_FLOAT0, PSB_TYPE_INTEGER_N,
-    PSB_TYPE_NULL, PSB_TYPE_RESOURCE_N, PSB_TYPE_STRING_N, PSB_TYPE_TRUE, PsbCompilerArray,
-    PsbCompilerBinaryTree, PsbCompilerBool, PsbCompilerDecimal, PsbCompilerNumber,
-    PsbCompilerResource, PsbCompilerString, PsbExtraResource, PsbResource,
+    PSB_TYPE_NULL, PSB_TYPE_RESOURCE_N, PSB_TYPE_STRING_N, PSB_TYPE_TRUE, PsbBytes,
+    PsbCompilerArray, PsbCompilerBinaryTree, PsbCompilerBool, PsbCompilerDecimal,
+    PsbCompilerNumber, PsbCompilerResource, PsbCompilerString, PsbExtraResource, PsbIntArray,
+    PsbResource,
     ser::{
         buffer::SerializerBuffer,
         map::{MapSerializer, StructSerializer},
         seq::SeqSerializer,
         special::SpecialValueSerializer,
-        string::StringCollector,
-        value::{ref_type::RefTypeSerializer, unit::UnitTypeSerializer},
+        spool::Spool,
+        value::{
+            bytes::BytesSerializer, int_array::IntArraySerializer, ref_type::RefTypeSerializer,
+            unit::UnitTypeSerializer,
+        },
+        variant::VariantSerializer,
     },
     util::{get_n, get_uint_n},
 };
 
 /// Serializes `value` into the given [`Buffer`], preparing it for writing to a stream.
 ///
-/// This function performs two passes over `value`:
-/// 1. A string-collection pass that builds the sorted name/string tables.
-/// 2. A binary-encoding pass that writes PSB-formatted bytes into `buf`.
+/// `value` is serialized once, collecting names and strings as they are
+/// encountered. The tree is then encoded with the ids of the sorted name and string
+/// tables.
 ///
 /// The resulting buffer can be passed to [`PsbWriter::new_with_buffer`] to produce a
 /// complete PSB file.
 ///
+/// With the `rayon` feature, the children of large [`PsbValue`] lists and objects,
+/// including those nested in typed models, are encoded on the rayon thread pool.
+/// The output is identical to sequential serialization.
+///
+/// [`PsbValue`]: crate::value::PsbValue
 /// [`PsbWriter::new_with_buffer`]: crate::psb::write::PsbWriter::new_with_buffer
 pub fn serialize(value: &impl Serialize, buf: &mut Buffer) -> Result<(), Error> {
-    value.serialize(StringCollector(buf))?;
-    buf.names.sort_unstable();
-    buf.strings.sort_unstable();
-    value.serialize(Serializer(State {
-        buf,
-        ser: &mut SerializerBuffer::new(),
-    }))?;
-    Ok(())
+    let serialize = |buf: &mut Buffer| {
+        value.serialize(Serializer(State {
+            buf,
+            ser: &mut SerializerBuffer::new(),
+            spool: None,
+        }))
+    };
+
+    #[cfg(feature = "rayon")]
+    par::with_target(buf.dedup, || serialize(buf))?;
+    #[cfg(not(feature = "rayon"))]
+    serialize(buf)?;
+
+    buf.resolve()
+}
+
+/// A serialized value tree that [`PsbWriter`] can write.
+///
+/// [`PsbWriter`]: crate::psb::write::PsbWriter
+pub(crate) trait EncodedTree {
+    /// Returns the sorted name table.
+    fn names(&self) -> &Slice<SmolStr>;
+
+    /// Returns the sorted string table.
+    fn strings(&self) -> &Slice<SmolStr>;
+
+    /// Returns one past the highest resource and extra resource index referenced.
+    fn resource_end(&self) -> (u64, u64);
+
+    /// Takes the data of the resources collected while serializing, by index.
+    fn take_resources(&mut self) -> Vec<Vec<u8>>;
+
+    /// Writes the encoded tree to `stream`, collecting its resource references into
+    /// `refs` if given.
+    fn write_tree(
+        &mut self,
+        stream: &mut dyn Write,
+        refs: Option<&mut Vec<ResourceRef>>,
+    ) -> io::Result<()>;
 }
 
 struct State<'a> {
     buf: &'a mut Buffer,
     ser: &'a mut SerializerBuffer,
+    /// Destination of finished lists and objects, when serializing to a spool
+    spool: Option<&'a mut dyn Spool>,
 }
 
 impl State<'_> {
@@ -66,6 +119,37 @@ impl State<'_> {
         State {
             buf: self.buf,
             ser: self.ser,
+            spool: match &mut self.spool {
+                Some(spool) => Some(&mut **spool),
+                None => None,
+            },
+        }
+    }
+
+    /// Finishes the list or object at `value_index`, see [`Buffer::finish_object`].
+    fn finish_object(
+        &mut self,
+        value_index: usize,
+        key_start: Option<usize>,
+        index_start: usize,
+        slot_start: usize,
+    ) -> Result<(), Error> {
+        match &mut self.spool {
+            Some(spool) => spool::spool_object(
+                self.buf,
+                self.ser,
+                &mut **spool,
+                value_index,
+                key_start,
+                index_start,
+                slot_start,
+            ),
+
+            None => {
+                self.buf
+                    .finish_object(value_index, key_start, index_start, slot_start);
+                Ok(())
+            }
         }
     }
 }
@@ -79,10 +163,10 @@ impl<'a> serde::Serializer for Serializer<'a> {
     type SerializeSeq = SeqSerializer<'a>;
     type SerializeTuple = SeqSerializer<'a>;
     type SerializeTupleStruct = SeqSerializer<'a>;
-    type SerializeTupleVariant = SeqSerializer<'a>;
+    type SerializeTupleVariant = VariantSerializer<SeqSerializer<'a>>;
     type SerializeMap = MapSerializer<'a>;
     type SerializeStruct = StructSerializer<'a>;
-    type SerializeStructVariant = MapSerializer<'a>;
+    type SerializeStructVariant = VariantSerializer<MapSerializer<'a>>;
 
     fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
         self.0.buf.write_value(|bytes| {
@@ -167,16 +251,17 @@ impl<'a> serde::Serializer for Serializer<'a> {
     }
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
-        let index = self
-            .0
-            .buf
-            .strings
-            .get_index_of(v)
-            .ok_or(Error::InvalidKey)?;
+        let strings = &mut self.0.buf.strings;
+        let index = match strings.get_index_of(v) {
+            Some(index) => index,
+            None => strings.insert_full(SmolStr::new(v)).0,
+        };
         let n = get_uint_n(index as _);
         if n > 4 {
             return Err(Error::IndexOverflow);
         }
+        // Spooled values cannot be resized once the final id is known.
+        let n = if self.0.spool.is_some() { 4 } else { n };
 
         self.0.buf.write_value(|bytes| {
             bytes.write_u8(PSB_TYPE_STRING_N + n)?;
@@ -220,9 +305,9 @@ impl<'a> serde::Serializer for Serializer<'a> {
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
+        variant: &'static str,
     ) -> Result<Self::Ok, Self::Error> {
-        self.serialize_unit()
+        self.serialize_str(variant)
     }
 
     fn serialize_newtype_struct<V>(
@@ -233,6 +318,15 @@ impl<'a> serde::Serializer for Serializer<'a> {
     where
         V: ?Sized + serde::Serialize,
     {
+        #[cfg(feature = "rayon")]
+        if _name == par::PAR_MARKER
+            && self.0.spool.is_none()
+            && let Some(encoded) = par::take_encoded()
+        {
+            self.0.buf.append(encoded)?;
+            return Ok(());
+        }
+
         value.serialize(self)
     }
 
@@ -240,13 +334,15 @@ impl<'a> serde::Serializer for Serializer<'a> {
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
+        variant: &'static str,
         value: &V,
     ) -> Result<Self::Ok, Self::Error>
     where
         V: ?Sized + serde::Serialize,
     {
-        value.serialize(self)
+        let mut map = self.serialize_map(Some(1))?;
+        map.serialize_entry(variant, value)?;
+        map.end()
     }
 
     fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
@@ -269,10 +365,10 @@ impl<'a> serde::Serializer for Serializer<'a> {
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
+        variant: &'static str,
         len: usize,
     ) -> Result<Self::SerializeTupleVariant, Self::Error> {
-        self.serialize_seq(Some(len))
+        VariantSerializer::tuple(self.0, variant, len)
     }
 
     fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
@@ -295,6 +391,16 @@ impl<'a> serde::Serializer for Serializer<'a> {
                 RefTypeSerializer::new(name, PSB_TYPE_EXTRA_N, self.0.buf),
             ))),
 
+            PsbBytes::MARKER => Ok(StructSerializer::Bytes(SpecialValueSerializer::new(
+                name,
+                BytesSerializer::new(self.0.buf),
+            ))),
+
+            PsbIntArray::MARKER => Ok(StructSerializer::IntArray(SpecialValueSerializer::new(
+                name,
+                IntArraySerializer::new(self.0.buf),
+            ))),
+
             PsbCompilerNumber::MARKER => Ok(StructSerializer::UnitTy(SpecialValueSerializer::new(
                 name,
                 UnitTypeSerializer::new(name, PSB_COMPILER_INTEGER, self.0.buf),
@@ -344,9 +450,9 @@ impl<'a> serde::Serializer for Serializer<'a> {
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
+        variant: &'static str,
         len: usize,
     ) -> Result<Self::SerializeStructVariant, Self::Error> {
-        self.serialize_map(Some(len))
+        VariantSerializer::struct_(self.0, variant, len)
     }
 }
@@ -0,0 +1,183 @@
+//! Parallel serialization of [`PsbValue`] trees.
+//!
+//! While [`serialize`](crate::value::ser::serialize) runs, a large [`PsbValue`] list
+//! or object is encoded on the rayon thread pool into a separate [`Buffer`], then
+//! handed to the serializer as a [`PAR_MARKER`] newtype struct which appends it.
+//! Other values, including sequences of typed models, are serialized on the
+//! calling thread.
+
+use core::{
+    borrow::Borrow,
+    cell::{Cell, RefCell},
+};
+
+use rayon::prelude::*;
+use scopeguard::defer;
+use serde::Serialize;
+
+use crate::value::{
+    PsbValue,
+    impls::serialize_plain,
+    ser::{Buffer, Error, Serializer, State, buffer::SerializerBuffer},
+};
+
+/// Name of the newtype struct handing an encoded [`PsbValue`] to the serializer.
+pub(crate) const PAR_MARKER: &str = "__PSB@PAR@VALUE";
+
+/// Minimum number of entries of a list or object to serialize its children on
+/// worker threads.
+const PAR_MIN_LEN: usize = 64;
+
+thread_local! {
+    /// Dedup setting of the buffer being serialized on this thread, if any
+    static TARGET: Cell<Option<bool>> = const { Cell::new(None) };
+    /// Value encoded for the next [`PAR_MARKER`] newtype struct
+    static ENCODED: RefCell<Option<Buffer>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with large [`PsbValue`] containers encoded in parallel, for a buffer
+/// with the `dedup` setting.
+pub(crate) fn with_target<R>(dedup: bool, f: impl FnOnce() -> R) -> R {
+    let previous = TARGET.replace(Some(dedup));
+    defer! {
+        TARGET.set(previous);
+    }
+    f()
+}
+
+/// Encodes `value` in parallel if it is a large list or object serialized by
+/// [`serialize`](crate::value::ser::serialize).
+pub(crate) fn encode(value: &PsbValue) -> Option<Buffer> {
+    let len = match value {
+        PsbValue::List(list) => list.len(),
+        PsbValue::Object(map) => map.len(),
+        _ => return None,
+    };
+    let dedup = TARGET.get().filter(|_| len >= PAR_MIN_LEN)?;
+
+    let mut buf = Buffer::new();
+    buf.set_dedup(dedup);
+    // Errors are reported by serializing the value sequentially.
+    serialize_value(value, &mut buf, &mut SerializerBuffer::new()).ok()?;
+    Some(buf)
+}
+
+/// Serializes `value` already encoded into `encoded`.
+pub(crate) fn serialize_encoded<S>(
+    encoded: Buffer,
+    value: &PsbValue,
+    se: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    ENCODED.set(Some(encoded));
+    se.serialize_newtype_struct(PAR_MARKER, &Sequential(value))
+}
+
+/// Takes the value encoded for a [`PAR_MARKER`] newtype struct.
+pub(crate) fn take_encoded() -> Option<Buffer> {
+    ENCODED.take()
+}
+
+/// Serializes a value without parallel encoding, for serializers other than
+/// [`Serializer`] receiving a [`PAR_MARKER`] newtype struct.
+struct Sequential<'a>(&'a PsbValue);
+
+impl Serialize for Sequential<'_> {
+    fn serialize<S>(&self, se: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        ENCODED.take();
+        let previous = TARGET.take();
+        defer! {
+            TARGET.set(previous);
+        }
+        serialize_plain(self.0, se)
+    }
+}
+
+fn serialize_value(
+    value: &PsbValue,
+    buf: &mut Buffer,
+    ser: &mut SerializerBuffer,
+) -> Result<(), Error> {
+    match value {
+        PsbValue::List(list) => {
+            let list_index = buf.push_placeholder();
+            let temp_index_start = ser.map_indexes.len();
+            serialize_children(list, buf, ser)?;
+
+            State {
+                buf,
+                ser,
+                spool: None,
+            }
+            .end_list(list_index, temp_index_start)
+        }
+
+        PsbValue::Object(map) => {
+            let map_index = buf.push_placeholder();
+            let key_start = ser.keys.len();
+            let map_index_start = ser.map_indexes.len();
+            for name in map.keys() {
+                let id = buf.insert_name(name)?;
+                ser.keys.push(id);
+            }
+            serialize_children(&map.values().collect::<Vec<_>>(), buf, ser)?;
+
+            State {
+                buf,
+                ser,
+                spool: None,
+            }
+            .end_map(map_index, key_start, map_index_start, map.len())
+        }
+
+        _ => value.serialize(Serializer(State {
+            buf,
+            ser,
+            spool: None,
+        })),
+    }
+}
+
+/// Serializes `children` in order, pushing their value indexes to `ser.map_indexes`.
+fn serialize_children<T: Borrow<PsbValue> + Sync>(
+    children: &[T],
+    buf: &mut Buffer,
+    ser: &mut SerializerBuffer,
+) -> Result<(), Error> {
+    if children.len() < PAR_MIN_LEN {
+        for child in children {
+            ser.map_indexes.push(buf.values.len());
+            serialize_value(child.borrow(), buf, ser)?;
+        }
+        return Ok(());
+    }
+
+    let dedup = buf.dedup;
+    let parts = children
+        .par_iter()
+        .try_fold(
+            || {
+                let mut buf = Buffer::new();
+                buf.set_dedup(dedup);
+                (buf, SerializerBuffer::new(), vec![])
+            },
+            |(mut buf, mut ser, mut roots), child| {
+                roots.push(buf.values.len());
+                serialize_value(child.borrow(), &mut buf, &mut ser)?;
+                Ok::<_, Error>((buf, ser, roots))
+            },
+        )
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (part, _, roots) in parts {
+        let value_start = buf.append(part)?;
+        ser.map_indexes
+            .extend(roots.into_iter().map(|root| root + value_start));
+    }
+    Ok(())
+}
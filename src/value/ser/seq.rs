@@ -1,19 +1,9 @@
-use byteorder::WriteBytesExt;
-use serde::ser::{SerializeSeq, SerializeTuple, SerializeTupleStruct, SerializeTupleVariant};
-
-use crate::value::{
-    PSB_TYPE_LIST,
-    ser::{
-        Error, Serializer, State,
-        buffer::{BufferObject, BufferValue},
-    },
-    util::write_uint_array,
-};
+use serde::ser::{SerializeSeq, SerializeTuple, SerializeTupleStruct};
+
+use crate::value::ser::{Error, Serializer, State};
 
 pub struct SeqSerializer<'a> {
-    len: usize,
     list_index: usize,
-    data_start: usize,
     temp_index_start: usize,
     state: State<'a>,
 }
@@ -26,18 +16,21 @@ impl<'a> SeqSerializer<'a> {
             state.ser.map_indexes.reserve(len);
         }
 
-        let list_index = state.buf.values.len();
-        state.buf.values.push(BufferValue::Invalid);
-        let data_start = state.buf.bytes.len();
+        let list_index = state.buf.push_placeholder();
         let temp_index_start = state.ser.map_indexes.len();
         Self {
-            len: 0,
             list_index,
-            data_start,
             temp_index_start,
             state,
         }
     }
+
+    /// Finishes the list, returning the state it was serialized with.
+    pub fn finish(mut self) -> Result<State<'a>, Error> {
+        self.state
+            .end_list(self.list_index, self.temp_index_start)?;
+        Ok(self.state)
+    }
 }
 
 impl<'a> SerializeSeq for SeqSerializer<'a> {
@@ -48,8 +41,6 @@ impl<'a> SerializeSeq for SeqSerializer<'a> {
     where
         T: ?Sized + serde::Serialize,
     {
-        self.len += 1;
-
         let index = self.state.buf.values.len();
         value.serialize(Serializer(self.state.reborrow_mut()))?;
         self.state.ser.map_indexes.push(index);
@@ -57,60 +48,33 @@ impl<'a> SerializeSeq for SeqSerializer<'a> {
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        let mut offset = 0;
-        for i in 0..self.len {
-            let value_index = self.state.ser.map_indexes[self.temp_index_start + i];
-            self.state.buf.indexes.push(value_index);
-            self.state.ser.offsets.push(offset);
-            offset += self.state.buf.values[value_index].size(self.state.buf) as u64;
-        }
-        let index_start = self.state.buf.indexes.len();
-        self.state
-            .buf
-            .indexes
-            .extend(self.state.ser.map_indexes.drain(self.temp_index_start..));
-
-        let header_start = self.state.buf.bytes.len();
-        self.state.buf.bytes.write_u8(PSB_TYPE_LIST)?;
-        write_uint_array(&mut self.state.buf.bytes, &self.state.ser.offsets)?;
-        let header_end = self.state.buf.bytes.len();
-        self.state.ser.offsets.clear();
-
-        let index = self.state.buf.objects.len();
-        self.state.buf.objects.push(BufferObject {
-            len: self.len,
-            data_start: self.data_start,
-            header_start,
-            header_end,
-            index_start,
-        });
-
-        self.state.buf.values[self.list_index] = BufferValue::Object { index };
-        Ok(())
+        self.finish().map(drop)
     }
 }
 
-impl SerializeTuple for SeqSerializer<'_> {
-    type Ok = <Self as SerializeSeq>::Ok;
-    type Error = <Self as SerializeSeq>::Error;
-
-    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
-    where
-        T: ?Sized + serde::Serialize,
-    {
-        SerializeSeq::serialize_element(self, value)
-    }
-
-    fn end(self) -> Result<Self::Ok, Self::Error> {
-        SerializeSeq::end(self)
+impl State<'_> {
+    /// Finishes the list at `list_index` with the elements from `temp_index_start`.
+    pub(super) fn end_list(
+        &mut self,
+        list_index: usize,
+        temp_index_start: usize,
+    ) -> Result<(), Error> {
+        let index_start = self.buf.indexes.len();
+        let slot_start = self.buf.slots.len();
+        self.buf.push_children(
+            self.ser.map_indexes.drain(temp_index_start..),
+            &mut self.ser.seen,
+        );
+
+        self.finish_object(list_index, None, index_start, slot_start)
     }
 }
 
-impl SerializeTupleStruct for SeqSerializer<'_> {
+impl SerializeTuple for SeqSerializer<'_> {
     type Ok = <Self as SerializeSeq>::Ok;
     type Error = <Self as SerializeSeq>::Error;
 
-    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
         T: ?Sized + serde::Serialize,
     {
@@ -122,7 +86,7 @@ impl SerializeTupleStruct for SeqSerializer<'_> {
     }
 }
 
-impl SerializeTupleVariant for SeqSerializer<'_> {
+impl SerializeTupleStruct for SeqSerializer<'_> {
     type Ok = <Self as SerializeSeq>::Ok;
     type Error = <Self as SerializeSeq>::Error;
 
@@ -0,0 +1,372 @@
+//! Serialization of value trees through a spool stream.
+
+use std::{
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    mem,
+};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use indexmap::set::Slice;
+use serde::Serialize;
+use smol_str::SmolStr;
+
+use crate::value::{
+    PSB_TYPE_EXTRA_N, PSB_TYPE_INTEGER_ARRAY_N, PSB_TYPE_LIST, PSB_TYPE_OBJECT,
+    PSB_TYPE_RESOURCE_N, PSB_TYPE_STRING_N,
+    ser::{
+        Buffer, EncodedTree, Error, ResourceRef, Serializer, State,
+        buffer::{BufferValue, SerializerBuffer},
+    },
+    util::{write_uint_array, write_uint_array_n},
+};
+
+/// Spool record item of a child value encoded in the record
+const ITEM_VALUE: u8 = 0;
+/// Spool record item of a child list or object in another record
+const ITEM_RECORD: u8 = 1;
+
+/// Serializes `value` through `spool`, writing each list and object to it as soon as
+/// it is finished.
+///
+/// Unlike [`serialize`], which holds the whole encoded tree in a [`Buffer`], only
+/// the children of unfinished lists and objects are kept in memory. The returned
+/// [`SpooledTree`] reassembles the spooled subtrees when written.
+///
+/// String ids and object names are encoded with 4 bytes, as the final ids are only
+/// known once the whole value is serialized. Identical subtrees are not
+/// deduplicated.
+///
+/// `spool` is written from its current position, and is typically a temporary file.
+///
+/// [`serialize`]: crate::value::ser::serialize
+pub fn serialize_spooled<S: Read + Write + Seek>(
+    value: &impl Serialize,
+    mut spool: S,
+) -> Result<SpooledTree<S>, Error> {
+    let position = spool.stream_position()?;
+    let mut buf = Buffer::new();
+    let mut writer = SpoolWriter {
+        stream: BufWriter::new(&mut spool),
+        position,
+    };
+    value.serialize(Serializer(State {
+        buf: &mut buf,
+        ser: &mut SerializerBuffer::new(),
+        spool: Some(&mut writer),
+    }))?;
+    writer.stream.flush()?;
+    drop(writer);
+
+    let (name_ids, string_ids) = buf.sort_tables(None);
+    Ok(SpooledTree {
+        buf,
+        name_ids,
+        string_ids,
+        spool,
+    })
+}
+
+/// A value tree serialized by [`serialize_spooled`], with its lists and objects
+/// held in a spool stream.
+///
+/// Pass it to [`PsbWriter::with_options_and_spooled`] to produce a complete PSB
+/// file.
+///
+/// [`PsbWriter::with_options_and_spooled`]: crate::psb::write::PsbWriter::with_options_and_spooled
+#[derive(Debug)]
+pub struct SpooledTree<S> {
+    /// Tables, and the root value unless it is spooled
+    buf: Buffer,
+    /// Final id of each name by insertion index
+    name_ids: Vec<u32>,
+    /// Final id of each string by insertion index
+    string_ids: Vec<u32>,
+    spool: S,
+}
+
+impl<S: Read + Seek> SpooledTree<S> {
+    /// Returns a slice of all collected object-key names in their serialized (sorted) order.
+    pub fn names(&self) -> &Slice<SmolStr> {
+        self.buf.names()
+    }
+
+    /// Returns a slice of all collected string values in their serialized (sorted) order.
+    pub fn strings(&self) -> &Slice<SmolStr> {
+        self.buf.strings()
+    }
+
+    /// Writes the serialized PSB value tree to `stream`, starting from the root value.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::Error`] if reading the spool or writing to `stream` fails.
+    pub fn write(&mut self, stream: &mut impl Write) -> io::Result<()> {
+        self.write_tree(stream, None)
+    }
+
+    /// Consumes the [`SpooledTree`] and returns the spool stream.
+    #[inline]
+    pub fn into_inner(self) -> S {
+        self.spool
+    }
+}
+
+impl<S: Read + Seek> EncodedTree for SpooledTree<S> {
+    fn names(&self) -> &Slice<SmolStr> {
+        self.buf.names()
+    }
+
+    fn strings(&self) -> &Slice<SmolStr> {
+        self.buf.strings()
+    }
+
+    fn resource_end(&self) -> (u64, u64) {
+        (self.buf.resource_end, self.buf.extra_end)
+    }
+
+    fn take_resources(&mut self) -> Vec<Vec<u8>> {
+        mem::take(&mut self.buf.resources)
+    }
+
+    fn write_tree(
+        &mut self,
+        stream: &mut dyn Write,
+        refs: Option<&mut Vec<ResourceRef>>,
+    ) -> io::Result<()> {
+        let mut writer = TreeWriter {
+            spool: BufReader::new(&mut self.spool),
+            name_ids: &self.name_ids,
+            string_ids: &self.string_ids,
+            stream,
+            offset: 0,
+            refs,
+        };
+
+        match self.buf.values.first() {
+            None => Ok(()),
+            Some(&BufferValue::Value { data_start, size }) => {
+                writer.write_value(&self.buf.bytes[data_start..][..size as usize])
+            }
+            Some(&BufferValue::Spooled { position, .. }) => writer.write_record(position),
+            Some(_) => Err(ErrorKind::InvalidData.into()),
+        }
+    }
+}
+
+/// Destination of the records of finished lists and objects.
+pub(crate) trait Spool {
+    /// Appends `record` and returns its position.
+    fn append(&mut self, record: &[u8]) -> io::Result<u64>;
+}
+
+struct SpoolWriter<W: Write> {
+    stream: BufWriter<W>,
+    position: u64,
+}
+
+impl<W: Write> Spool for SpoolWriter<W> {
+    fn append(&mut self, record: &[u8]) -> io::Result<u64> {
+        let position = self.position;
+        self.stream.write_all(record)?;
+        self.position += record.len() as u64;
+        Ok(position)
+    }
+}
+
+/// Writes the list or object at `value_index` to `spool`, and releases its children.
+///
+/// The record holds the length prefixed header, followed by the number of children
+/// and an item per child, either its encoded value or the position of its record.
+pub(super) fn spool_object(
+    buf: &mut Buffer,
+    ser: &mut SerializerBuffer,
+    spool: &mut dyn Spool,
+    value_index: usize,
+    key_start: Option<usize>,
+    index_start: usize,
+    slot_start: usize,
+) -> Result<(), Error> {
+    debug_assert!(!buf.dedup);
+
+    let children = &buf.indexes[index_start..];
+    let mut size = 0;
+    for &child in children {
+        ser.offsets.push(size as u64);
+        size += buf.values[child].size(buf);
+    }
+
+    let record = &mut ser.record;
+    record.clear();
+    record.extend_from_slice(&[0; 4]);
+    match key_start {
+        Some(key_start) => {
+            record.push(PSB_TYPE_OBJECT);
+            write_uint_array_n(record, &buf.keys[key_start..], 4)?;
+        }
+        None => record.push(PSB_TYPE_LIST),
+    }
+    write_uint_array(record, &ser.offsets)?;
+    ser.offsets.clear();
+
+    let header_len = record.len() - 4;
+    record[..4].copy_from_slice(&(header_len as u32).to_le_bytes());
+    record.extend_from_slice(&(children.len() as u32).to_le_bytes());
+
+    let mut bytes_start = buf.bytes.len();
+    for &child in children {
+        match buf.values[child] {
+            BufferValue::Value { data_start, size } => {
+                bytes_start = bytes_start.min(data_start);
+                record.push(ITEM_VALUE);
+                record.extend_from_slice(&size.to_le_bytes());
+                record.extend_from_slice(&buf.bytes[data_start..][..size as usize]);
+            }
+            BufferValue::Spooled { position, .. } => {
+                record.push(ITEM_RECORD);
+                record.extend_from_slice(&position.to_le_bytes());
+            }
+            BufferValue::Invalid | BufferValue::Object { .. } => {
+                return Err(io::Error::from(ErrorKind::InvalidData).into());
+            }
+        }
+    }
+
+    let position = spool.append(record)?;
+    buf.values.truncate(value_index + 1);
+    buf.values[value_index] = BufferValue::Spooled {
+        position,
+        size: header_len + size,
+    };
+    buf.bytes.truncate(bytes_start);
+    buf.indexes.truncate(index_start);
+    buf.slots.truncate(slot_start);
+    if let Some(key_start) = key_start {
+        buf.keys.truncate(key_start);
+    }
+
+    Ok(())
+}
+
+/// Child of a spooled list or object.
+enum Item {
+    /// Encoded value, at a range of the values read from the record
+    Value {
+        start: usize,
+        size: usize,
+    },
+    Record(u64),
+}
+
+struct TreeWriter<'a, R, W: ?Sized> {
+    spool: R,
+    name_ids: &'a [u32],
+    string_ids: &'a [u32],
+    stream: &'a mut W,
+    /// Offset from the start of the written tree
+    offset: usize,
+    refs: Option<&'a mut Vec<ResourceRef>>,
+}
+
+impl<R: Read + Seek, W: Write + ?Sized> TreeWriter<'_, R, W> {
+    fn write_record(&mut self, position: u64) -> io::Result<()> {
+        self.spool.seek(SeekFrom::Start(position))?;
+        let header_len = self.spool.read_u32::<LittleEndian>()?;
+        let mut header = vec![0; header_len as usize];
+        self.spool.read_exact(&mut header)?;
+
+        let len = self.spool.read_u32::<LittleEndian>()?;
+        let mut items = Vec::with_capacity(len as usize);
+        let mut values = vec![];
+        for _ in 0..len {
+            items.push(match self.spool.read_u8()? {
+                ITEM_VALUE => {
+                    let size = self.spool.read_u32::<LittleEndian>()? as usize;
+                    let start = values.len();
+                    values.resize(start + size, 0);
+                    self.spool.read_exact(&mut values[start..])?;
+                    Item::Value { start, size }
+                }
+                ITEM_RECORD => Item::Record(self.spool.read_u64::<LittleEndian>()?),
+                _ => return Err(ErrorKind::InvalidData.into()),
+            });
+        }
+
+        if header.first() == Some(&PSB_TYPE_OBJECT) {
+            self.remap_keys(&mut header)?;
+        }
+        self.write(&header)?;
+
+        for item in items {
+            match item {
+                Item::Value { start, size } => self.write_value(&values[start..][..size])?,
+                Item::Record(position) => self.write_record(position)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the name ids of an object header with the final ones.
+    fn remap_keys(&self, header: &mut [u8]) -> io::Result<()> {
+        let len_n = header[1] - PSB_TYPE_INTEGER_ARRAY_N;
+        let mut len = [0; 8];
+        len[..len_n as usize].copy_from_slice(&header[2..][..len_n as usize]);
+        let len = u64::from_le_bytes(len) as usize;
+
+        let keys = &mut header[3 + len_n as usize..][..len * 4];
+        for key in keys.chunks_exact_mut(4) {
+            let id = u32::from_le_bytes([key[0], key[1], key[2], key[3]]);
+            let id = self
+                .name_ids
+                .get(id as usize)
+                .ok_or(ErrorKind::InvalidData)?;
+            key.copy_from_slice(&id.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    fn write_value(&mut self, value: &[u8]) -> io::Result<()> {
+        match value[0] {
+            ty if ty == PSB_TYPE_STRING_N + 4 => {
+                let id = u32::from_le_bytes([value[1], value[2], value[3], value[4]]);
+                let id = self
+                    .string_ids
+                    .get(id as usize)
+                    .ok_or(ErrorKind::InvalidData)?;
+                let mut value = [ty, 0, 0, 0, 0];
+                value[1..].copy_from_slice(&id.to_le_bytes());
+                return self.write(&value);
+            }
+
+            ty if (PSB_TYPE_RESOURCE_N + 1..=PSB_TYPE_RESOURCE_N + 4).contains(&ty) => {
+                self.push_ref(value, false)
+            }
+            ty if (PSB_TYPE_EXTRA_N + 1..=PSB_TYPE_EXTRA_N + 4).contains(&ty) => {
+                self.push_ref(value, true)
+            }
+            _ => {}
+        }
+
+        self.write(value)
+    }
+
+    fn push_ref(&mut self, value: &[u8], extra: bool) {
+        if let Some(refs) = &mut self.refs {
+            let width = value.len() - 1;
+            let mut index = [0; 4];
+            index[..width].copy_from_slice(&value[1..]);
+            refs.push(ResourceRef {
+                offset: self.offset + 1,
+                width: width as u8,
+                index: u32::from_le_bytes(index),
+                extra,
+            });
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.stream.write_all(bytes)?;
+        self.offset += bytes.len();
+        Ok(())
+    }
+}
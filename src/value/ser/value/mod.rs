@@ -1,2 +1,4 @@
+pub mod bytes;
+pub mod int_array;
 pub mod ref_type;
 pub mod unit;
@@ -4,6 +4,7 @@ use byteorder::WriteBytesExt;
 use serde::ser::Impossible;
 
 use crate::value::{
+    PSB_TYPE_EXTRA_N, PSB_TYPE_RESOURCE_N,
     ser::{Error, buffer::Buffer},
     util::get_uint_n,
 };
@@ -33,6 +34,19 @@ impl<'a> serde::Serializer for RefTypeSerializer<'a> {
     type SerializeStructVariant = Impossible<Self::Ok, Error>;
 
     fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
+        let end = match self.ty {
+            PSB_TYPE_RESOURCE_N => {
+                if !self.buf.resources.is_empty() {
+                    return Err(Error::MixedResources);
+                }
+                self.buf.explicit_resources = true;
+                &mut self.buf.resource_end
+            }
+            PSB_TYPE_EXTRA_N => &mut self.buf.extra_end,
+            _ => unreachable!(),
+        };
+        *end = (*end).max(v as u64 + 1);
+
         let n = get_uint_n(v as _);
         self.buf.write_value(|bytes| {
             bytes.write_u8(self.ty + n)?;
@@ -0,0 +1,82 @@
+use serde::ser::{SerializeMap, SerializeSeq, SerializeStructVariant, SerializeTupleVariant};
+
+use crate::value::ser::{Error, State, map::MapSerializer, seq::SeqSerializer};
+
+/// Serializer of a tuple or struct variant, written as an object with the variant
+/// name as its single key.
+pub struct VariantSerializer<S> {
+    map_index: usize,
+    key_start: usize,
+    map_index_start: usize,
+    inner: S,
+}
+
+impl<'a> VariantSerializer<SeqSerializer<'a>> {
+    pub fn tuple(state: State<'a>, variant: &str, len: usize) -> Result<Self, Error> {
+        Self::new(state, variant, |state| SeqSerializer::new(state, Some(len)))
+    }
+}
+
+impl<'a> VariantSerializer<MapSerializer<'a>> {
+    pub fn struct_(state: State<'a>, variant: &str, len: usize) -> Result<Self, Error> {
+        Self::new(state, variant, |state| MapSerializer::new(state, Some(len)))
+    }
+}
+
+impl<'a, S> VariantSerializer<S> {
+    /// Starts the enclosing object with the `variant` key, then the variant value
+    /// with `inner`.
+    fn new(
+        state: State<'a>,
+        variant: &str,
+        inner: impl FnOnce(State<'a>) -> S,
+    ) -> Result<Self, Error> {
+        let map_index = state.buf.push_placeholder();
+        let key_start = state.ser.keys.len();
+        let map_index_start = state.ser.map_indexes.len();
+
+        let id = state.buf.insert_name(variant)?;
+        state.ser.keys.push(id);
+        state.ser.map_indexes.push(state.buf.values.len());
+        Ok(Self {
+            map_index,
+            key_start,
+            map_index_start,
+            inner: inner(state),
+        })
+    }
+}
+
+impl SerializeTupleVariant for VariantSerializer<SeqSerializer<'_>> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
+    where
+        T: ?Sized + serde::Serialize,
+    {
+        SerializeSeq::serialize_element(&mut self.inner, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        let mut state = self.inner.finish()?;
+        state.end_map(self.map_index, self.key_start, self.map_index_start, 1)
+    }
+}
+
+impl SerializeStructVariant for VariantSerializer<MapSerializer<'_>> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error>
+    where
+        T: ?Sized + serde::Serialize,
+    {
+        SerializeMap::serialize_entry(&mut self.inner, key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        let mut state = self.inner.finish()?;
+        state.end_map(self.map_index, self.key_start, self.map_index_start, 1)
+    }
+}
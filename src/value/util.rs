@@ -8,13 +8,20 @@ pub fn read_uint_array(stream: &mut impl Read, buf: &mut Vec<u64>) -> Result<usi
     const PSB_TYPE_INTEGER_ARRAY_START: u8 = PSB_TYPE_INTEGER_ARRAY_N + 1;
     const PSB_TYPE_INTEGER_ARRAY_END: u8 = PSB_TYPE_INTEGER_ARRAY_N + 8;
 
-    let len_n = match stream.read_u8()? {
+    match stream.read_u8()? {
         ty @ PSB_TYPE_INTEGER_ARRAY_START..=PSB_TYPE_INTEGER_ARRAY_END => {
-            ty - PSB_TYPE_INTEGER_ARRAY_N
+            read_uint_array_items(stream, ty - PSB_TYPE_INTEGER_ARRAY_N, buf)
         }
-        ty => return Err(de::Error::InvalidValueType(ty)),
-    };
+        ty => Err(de::Error::InvalidValueType(ty)),
+    }
+}
 
+/// Reads the items of an uint array with a length of `len_n` bytes, after its type tag.
+pub fn read_uint_array_items(
+    stream: &mut impl Read,
+    len_n: u8,
+    buf: &mut Vec<u64>,
+) -> Result<usize, de::Error> {
     let len = read_partial_uint(stream, len_n)?;
     let item_byte_size = stream.read_u8()? - PSB_TYPE_INTEGER_ARRAY_N;
     buf.reserve(len as _);
@@ -25,20 +32,34 @@ pub fn read_uint_array(stream: &mut impl Read, buf: &mut Vec<u64>) -> Result<usi
 }
 
 pub fn write_uint_array(stream: &mut impl Write, buf: &[impl Into<u64> + Copy]) -> io::Result<()> {
-    let len_n = get_uint_n(buf.len() as _);
-    stream.write_u8(PSB_TYPE_INTEGER_ARRAY_N + len_n)?;
-    stream.write_all(&buf.len().to_le_bytes()[..len_n as _])?;
-
     let max_v = buf
         .iter()
         .copied()
         .map(Into::into)
         .max()
         .unwrap_or_default();
-    let max_n = get_uint_n(max_v);
-    stream.write_u8(PSB_TYPE_INTEGER_ARRAY_N + max_n)?;
+    write_uint_array_n(stream, buf, get_uint_n(max_v))
+}
+
+/// Returns the byte size of an uint array of `len` items up to `max`, as written by
+/// [`write_uint_array`].
+pub const fn uint_array_len(len: usize, max: u64) -> u64 {
+    2 + get_uint_n(len as _) as u64 + len as u64 * get_uint_n(max) as u64
+}
+
+/// Writes `buf` as an uint array with items of `n` bytes.
+pub fn write_uint_array_n(
+    stream: &mut impl Write,
+    buf: &[impl Into<u64> + Copy],
+    n: u8,
+) -> io::Result<()> {
+    let len_n = get_uint_n(buf.len() as _);
+    stream.write_u8(PSB_TYPE_INTEGER_ARRAY_N + len_n)?;
+    stream.write_all(&buf.len().to_le_bytes()[..len_n as _])?;
+
+    stream.write_u8(PSB_TYPE_INTEGER_ARRAY_N + n)?;
     for v in buf.iter().copied().map(Into::into) {
-        stream.write_all(&v.to_le_bytes()[..max_n as _])?;
+        stream.write_all(&v.to_le_bytes()[..n as _])?;
     }
 
     Ok(())
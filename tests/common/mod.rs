@@ -0,0 +1,81 @@
+//! Helpers shared by the integration tests.
+
+#![allow(dead_code)]
+
+use std::io::Cursor;
+
+use emote_psb::{
+    psb::{
+        error::PsbWriteError,
+        write::{PsbWriter, PsbWriterOptions},
+    },
+    value::{
+        PsbValue,
+        ser::{Buffer, serialize_spooled},
+    },
+};
+
+/// Value tree of a file written by [`write_with`].
+pub enum Tree<'t> {
+    /// A value serialized by the writer
+    Value(&'t PsbValue),
+    /// A value already serialized into a buffer
+    Buffer(&'t mut Buffer),
+    /// A value serialized into a spool
+    Spooled(&'t PsbValue),
+}
+
+impl<'t> From<&'t PsbValue> for Tree<'t> {
+    fn from(value: &'t PsbValue) -> Self {
+        Self::Value(value)
+    }
+}
+
+impl<'t> From<&'t mut Buffer> for Tree<'t> {
+    fn from(buf: &'t mut Buffer) -> Self {
+        Self::Buffer(buf)
+    }
+}
+
+/// Writes a PSB file of `tree`, attaching `resources` and `extra` resources in
+/// order. Returns the file and the indices of the attached resources.
+pub fn write_with<'t>(
+    options: &PsbWriterOptions,
+    tree: impl Into<Tree<'t>>,
+    resources: &[&[u8]],
+    extra: &[&[u8]],
+) -> Result<(Vec<u8>, Vec<usize>), PsbWriteError> {
+    let mut out = Cursor::new(Vec::new());
+    let mut spooled;
+    let mut writer = match tree.into() {
+        Tree::Value(value) => PsbWriter::with_options(options, value, &mut out)?,
+        Tree::Buffer(buf) => PsbWriter::with_options_and_buffer(options, buf, &mut out)?,
+        Tree::Spooled(value) => {
+            // The spool does not have to start empty.
+            let mut spool = Cursor::new(vec![0xff; 7]);
+            spool.set_position(7);
+            spooled = serialize_spooled(value, spool)?;
+            PsbWriter::with_options_and_spooled(options, &mut spooled, &mut out)?
+        }
+    };
+
+    let indices = resources
+        .iter()
+        .map(|&res| writer.add_resource_data(res))
+        .collect::<Result<_, _>>()?;
+    for &res in extra {
+        writer.add_extra_data(res)?;
+    }
+    writer.finish()?;
+    Ok((out.into_inner(), indices))
+}
+
+/// Writes a PSB file like [`write_with`], panicking on errors.
+pub fn write_psb<'t>(
+    options: &PsbWriterOptions,
+    tree: impl Into<Tree<'t>>,
+    resources: &[&[u8]],
+    extra: &[&[u8]],
+) -> Vec<u8> {
+    write_with(options, tree, resources, extra).unwrap().0
+}
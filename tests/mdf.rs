@@ -0,0 +1,353 @@
+use std::io::{BufReader, Cursor, Read, Write};
+
+use emote_psb::{
+    mdf::{
+        MdfCompression, MdfOptions, MdfReader, MdfWriter,
+        error::{MdfCreateError, MdfOpenError},
+    },
+    psb::{read::PsbFile, write::PsbWriter},
+    value::{PsbValue, number::PsbNumber},
+};
+
+fn sample_psb() -> Vec<u8> {
+    let root = PsbValue::List(vec![
+        PsbValue::String("sample".into()),
+        PsbValue::Number(PsbNumber::Integer(1234)),
+        PsbValue::Bool(true),
+    ]);
+
+    let mut buf = Cursor::new(Vec::new());
+    PsbWriter::new(3, false, &root, &mut buf)
+        .unwrap()
+        .finish()
+        .unwrap();
+    buf.into_inner()
+}
+
+/// Deterministic, moderately compressible data spanning many blocks.
+fn large_data(len: usize) -> Vec<u8> {
+    let mut state = 0x2545f491_u32;
+    (0..len)
+        .map(|i| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            if i % 7 < 4 {
+                (i / 64) as u8
+            } else {
+                state as u8
+            }
+        })
+        .collect()
+}
+
+fn write_mdf(options: &MdfOptions, data: &[u8]) -> Vec<u8> {
+    let mut writer = MdfWriter::with_options(Cursor::new(Vec::new()), options).unwrap();
+    // uneven writes to cross block boundaries
+    for chunk in data.chunks(10_000) {
+        writer.write_all(chunk).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+fn read_mdf(mdf: Vec<u8>) -> Vec<u8> {
+    let mut reader = MdfReader::open(Cursor::new(mdf)).unwrap();
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    out
+}
+
+fn mdf_roundtrip(compression: MdfCompression, data: &[u8]) -> Vec<u8> {
+    let mut writer = MdfWriter::with_compression(Cursor::new(Vec::new()), compression).unwrap();
+    writer.write_all(data).unwrap();
+    let mdf = writer.finish().unwrap().into_inner();
+
+    let mut reader = MdfReader::open(BufReader::new(Cursor::new(mdf))).unwrap();
+    assert_eq!(reader.compression(), compression);
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    out
+}
+
+#[test]
+fn mdf_zlib_roundtrip() {
+    let data = sample_psb();
+    assert_eq!(mdf_roundtrip(MdfCompression::Zlib, &data), data);
+}
+
+#[test]
+fn mdf_zlib_level_roundtrip() {
+    let data = sample_psb();
+    let mut writer = MdfWriter::new(Cursor::new(Vec::new()), 9).unwrap();
+    writer.write_all(&data).unwrap();
+    let mdf = writer.finish().unwrap().into_inner();
+
+    let mut reader = MdfReader::open(Cursor::new(mdf)).unwrap();
+    assert_eq!(reader.compression(), MdfCompression::Zlib);
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(out, data);
+}
+
+#[test]
+fn mdf_stored_roundtrip() {
+    let data = sample_psb();
+    assert_eq!(mdf_roundtrip(MdfCompression::Stored, &data), data);
+}
+
+#[test]
+fn mdf_stored_is_plain_psb() {
+    let data = sample_psb();
+    let mut writer =
+        MdfWriter::with_compression(Cursor::new(Vec::new()), MdfCompression::Stored).unwrap();
+    writer.write_all(&data).unwrap();
+    let mdf = writer.finish().unwrap().into_inner();
+    assert_eq!(&mdf[8..], &data[..]);
+    assert_eq!(
+        u32::from_le_bytes(mdf[4..8].try_into().unwrap()),
+        data.len() as u32
+    );
+
+    let mut reader = MdfReader::open(Cursor::new(mdf)).unwrap();
+    let mut psb = Vec::new();
+    reader.read_to_end(&mut psb).unwrap();
+    let mut psb = PsbFile::open(Cursor::new(psb)).unwrap();
+    assert_eq!(
+        psb.deserialize_root::<PsbValue>().unwrap(),
+        PsbValue::List(vec![
+            PsbValue::String("sample".into()),
+            PsbValue::Number(PsbNumber::Integer(1234)),
+            PsbValue::Bool(true),
+        ])
+    );
+}
+
+#[cfg(feature = "lz4")]
+#[test]
+fn mdf_lz4_roundtrip() {
+    let data = sample_psb();
+    assert_eq!(mdf_roundtrip(MdfCompression::Lz4, &data), data);
+}
+
+#[cfg(not(feature = "lz4"))]
+#[test]
+fn mdf_lz4_unsupported() {
+    assert!(!MdfCompression::Lz4.is_supported());
+    assert!(MdfWriter::with_compression(Cursor::new(Vec::new()), MdfCompression::Lz4).is_err());
+
+    let mut mdf = b"mdf\0\x08\0\0\0".to_vec();
+    mdf.extend_from_slice(&[0x04, 0x22, 0x4d, 0x18, 0x64, 0x40, 0xa7, 0x00]);
+    assert!(matches!(
+        MdfReader::open(Cursor::new(mdf)),
+        Err(MdfOpenError::UnsupportedCompression(MdfCompression::Lz4))
+    ));
+}
+
+#[test]
+fn mdf_detect_signatures() {
+    assert_eq!(
+        MdfCompression::detect(&[0x78, 0x9c, 0x00, 0x00]),
+        Some(MdfCompression::Zlib)
+    );
+    assert_eq!(
+        MdfCompression::detect(&[0x04, 0x22, 0x4d, 0x18]),
+        Some(MdfCompression::Lz4)
+    );
+    assert_eq!(
+        MdfCompression::detect(b"PSB\0"),
+        Some(MdfCompression::Stored)
+    );
+    assert_eq!(MdfCompression::detect(&[0xde, 0xad, 0xbe, 0xef]), None);
+    assert_eq!(MdfCompression::detect(&[0x78]), None);
+}
+
+#[test]
+fn mdf_unknown_compression() {
+    let mut mdf = b"mdf\0\x04\0\0\0".to_vec();
+    mdf.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+    assert!(matches!(
+        MdfReader::open(Cursor::new(mdf)),
+        Err(MdfOpenError::UnknownCompression)
+    ));
+}
+
+#[test]
+fn mdf_open_short_reads() {
+    let data = sample_psb();
+    for compression in [MdfCompression::Zlib, MdfCompression::Stored] {
+        let mut writer = MdfWriter::with_compression(Cursor::new(Vec::new()), compression).unwrap();
+        writer.write_all(&data).unwrap();
+        let mdf = writer.finish().unwrap().into_inner();
+
+        // Every read returns a single byte, fewer than the codec signature.
+        let mut reader = MdfReader::open(BufReader::with_capacity(1, Cursor::new(mdf))).unwrap();
+        assert_eq!(reader.compression(), compression);
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, data);
+    }
+}
+
+#[test]
+fn mdf_options_levels_roundtrip() {
+    let data = large_data(100_000);
+    for level in 0..=9 {
+        let mdf = write_mdf(&MdfOptions::new().level(level), &data);
+        assert_eq!(read_mdf(mdf), data, "failed for level {level}");
+    }
+}
+
+#[test]
+fn mdf_invalid_options() {
+    for options in [MdfOptions::new().level(10), MdfOptions::new().block_size(0)] {
+        assert!(matches!(
+            MdfWriter::with_options(Cursor::new(Vec::new()), &options),
+            Err(MdfCreateError::InvalidOption(_))
+        ));
+    }
+}
+
+#[test]
+fn mdf_parallel_roundtrip() {
+    let data = large_data(3_000_000);
+    let options = MdfOptions::new().threads(4).block_size(64 * 1024);
+    let mdf = write_mdf(&options, &data);
+    assert_eq!(read_mdf(mdf), data);
+}
+
+#[test]
+fn mdf_parallel_block_boundaries() {
+    let block_size = 4096;
+    for len in [
+        0,
+        1,
+        block_size - 1,
+        block_size,
+        block_size + 1,
+        block_size * 8,
+    ] {
+        let data = large_data(len);
+        let options = MdfOptions::new().threads(3).block_size(block_size);
+        let mdf = write_mdf(&options, &data);
+        assert_eq!(read_mdf(mdf), data, "failed for length {len}");
+    }
+}
+
+#[test]
+fn mdf_parallel_single_write_and_flush() {
+    let data = large_data(1_000_000);
+    let options = MdfOptions::new().threads(2).block_size(4096);
+    let mut writer = MdfWriter::with_options(Cursor::new(Vec::new()), &options).unwrap();
+    let (head, tail) = data.split_at(300_001);
+    writer.write_all(head).unwrap();
+    writer.flush().unwrap();
+    writer.write_all(tail).unwrap();
+    let mdf = writer.finish().unwrap().into_inner();
+    assert_eq!(read_mdf(mdf), data);
+}
+
+#[test]
+fn mdf_parallel_available_threads() {
+    let data = large_data(500_000);
+    let options = MdfOptions::new().threads(0).block_size(32 * 1024).level(1);
+    let mdf = write_mdf(&options, &data);
+    assert_eq!(read_mdf(mdf), data);
+}
+
+#[test]
+fn mdf_parallel_compresses() {
+    let data = large_data(1_000_000);
+    let options = MdfOptions::new().threads(4).block_size(128 * 1024);
+    let mdf = write_mdf(&options, &data);
+    assert!(mdf.len() < data.len());
+}
+
+#[cfg(feature = "zlib-rs")]
+mod zlib_rs_options {
+    use super::*;
+
+    use emote_psb::mdf::MdfStrategy;
+
+    fn read_mdf_with_dictionary(mdf: Vec<u8>, dictionary: &[u8]) -> Vec<u8> {
+        let mut reader = MdfReader::open_with_dictionary(Cursor::new(mdf), dictionary).unwrap();
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn mdf_strategies_roundtrip() {
+        let data = large_data(200_000);
+        for strategy in [
+            MdfStrategy::Default,
+            MdfStrategy::Filtered,
+            MdfStrategy::HuffmanOnly,
+            MdfStrategy::Rle,
+            MdfStrategy::Fixed,
+        ] {
+            let mdf = write_mdf(&MdfOptions::new().strategy(strategy), &data);
+            assert_eq!(read_mdf(mdf), data, "failed for {strategy:?}");
+        }
+    }
+
+    #[test]
+    fn mdf_window_bits_roundtrip() {
+        let data = large_data(200_000);
+        for window_bits in 9..=15 {
+            let options = MdfOptions::new().window_bits(window_bits);
+            let mdf = write_mdf(&options, &data);
+            assert_eq!(mdf[8] >> 4, window_bits - 8);
+            assert_eq!(read_mdf(mdf), data, "failed for window bits {window_bits}");
+
+            let mdf = write_mdf(&options.threads(2).block_size(16 * 1024), &data);
+            assert_eq!(mdf[8] >> 4, window_bits - 8);
+            assert_eq!(
+                read_mdf(mdf),
+                data,
+                "failed for parallel window bits {window_bits}"
+            );
+        }
+    }
+
+    #[test]
+    fn mdf_invalid_window_bits() {
+        for window_bits in [0, 8, 16] {
+            assert!(matches!(
+                MdfWriter::with_options(
+                    Cursor::new(Vec::new()),
+                    &MdfOptions::new().window_bits(window_bits)
+                ),
+                Err(MdfCreateError::InvalidOption("window_bits"))
+            ));
+        }
+    }
+
+    #[test]
+    fn mdf_dictionary_roundtrip() {
+        let dictionary = large_data(4096);
+        let data = large_data(300_000);
+
+        let mdf = write_mdf(&MdfOptions::new().dictionary(dictionary.clone()), &data);
+        assert_ne!(mdf[9] & 0x20, 0, "FDICT flag not set");
+        assert_eq!(read_mdf_with_dictionary(mdf, &dictionary), data);
+
+        let options = MdfOptions::new()
+            .dictionary(dictionary.clone())
+            .threads(4)
+            .block_size(32 * 1024);
+        let mdf = write_mdf(&options, &data);
+        assert_ne!(mdf[9] & 0x20, 0, "FDICT flag not set");
+        assert_eq!(read_mdf_with_dictionary(mdf.clone(), &dictionary), data);
+
+        // Without the dictionary, or with another one, the body cannot be read.
+        let mut out = Vec::new();
+        let mut reader = MdfReader::open(Cursor::new(mdf.clone())).unwrap();
+        assert!(reader.read_to_end(&mut out).is_err());
+        let mut reader = MdfReader::open_with_dictionary(Cursor::new(mdf), &b"other"[..]).unwrap();
+        assert!(reader.read_to_end(&mut out).is_err());
+
+        // Bodies without a preset dictionary are read as well.
+        let mdf = write_mdf(&MdfOptions::new(), &data);
+        assert_eq!(read_mdf_with_dictionary(mdf, &dictionary), data);
+    }
+}
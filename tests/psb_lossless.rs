@@ -0,0 +1,255 @@
+mod common;
+
+use std::io::{Cursor, Read};
+
+use emote_psb::{
+    psb::{
+        error::PsbWriteError,
+        lossless::PsbLossless,
+        read::PsbFile,
+        write::{PsbWriter, PsbWriterOptions},
+    },
+    value::{PsbValue, number::PsbNumber},
+};
+use indexmap::IndexMap;
+use smol_str::SmolStr;
+
+use common::write_psb;
+
+fn int(v: i64) -> PsbValue {
+    PsbValue::Number(PsbNumber::Integer(v))
+}
+
+fn object(entries: impl IntoIterator<Item = (&'static str, PsbValue)>) -> PsbValue {
+    PsbValue::Object(
+        entries
+            .into_iter()
+            .map(|(k, v)| (SmolStr::new(k), v))
+            .collect::<IndexMap<_, _>>(),
+    )
+}
+
+fn sample() -> PsbValue {
+    let frame = object([("x", int(1)), ("y", int(-300))]);
+    object([
+        ("name", PsbValue::String("motion".into())),
+        ("frames", PsbValue::List(vec![frame.clone(); 4])),
+        ("scale", PsbValue::Number(PsbNumber::Double(0.5))),
+        ("image", PsbValue::Resource(0)),
+        ("keys", PsbValue::IntArray(vec![0, 300, 70000])),
+    ])
+}
+
+fn write(version: u16, value: &PsbValue) -> Vec<u8> {
+    let extra: &[&[u8]] = if version > 3 { &[b"extra"] } else { &[] };
+    write_psb(
+        &PsbWriterOptions::new(version).dedup(true),
+        value,
+        &[b"pixels"],
+        extra,
+    )
+}
+
+fn read_lossless(data: &[u8]) -> PsbLossless {
+    PsbFile::open(Cursor::new(data))
+        .unwrap()
+        .read_lossless()
+        .unwrap()
+}
+
+fn write_lossless(psb: &PsbLossless) -> Vec<u8> {
+    let mut out = vec![];
+    psb.write(&mut out).unwrap();
+    out
+}
+
+fn read(data: Vec<u8>) -> PsbFile<Cursor<Vec<u8>>> {
+    PsbFile::open(Cursor::new(data)).unwrap()
+}
+
+/// Widens the root integer of a version 2 PSB file holding a single integer from
+/// one to two bytes, as a minimal encoder would never do.
+fn widen_root_integer(mut data: Vec<u8>) -> Vec<u8> {
+    let entrypoint = u32::from_le_bytes(data[36..40].try_into().unwrap()) as usize;
+    assert_eq!(data[entrypoint], 0x05);
+    data[entrypoint] = 0x06;
+    data.insert(entrypoint + 2, 0);
+
+    for slot in (12..40).step_by(4) {
+        let offset = u32::from_le_bytes(data[slot..slot + 4].try_into().unwrap());
+        if offset as usize > entrypoint {
+            data[slot..slot + 4].copy_from_slice(&(offset + 1).to_le_bytes());
+        }
+    }
+    data
+}
+
+#[test]
+fn lossless_unmodified_roundtrip() {
+    for version in [2, 3, 4] {
+        let data = write(version, &sample());
+        let psb = read_lossless(&data);
+        assert!(!psb.is_modified());
+        assert_eq!(psb.root, sample());
+        assert_eq!(write_lossless(&psb), data);
+    }
+}
+
+#[test]
+fn lossless_keeps_shared_offsets() {
+    let data = write(3, &sample());
+    let psb = read_lossless(&data);
+
+    // A plain rewrite loses the shared frames.
+    let mut plain = Cursor::new(Vec::new());
+    let mut writer = PsbWriter::new(3, false, &psb.root, &mut plain).unwrap();
+    writer.add_resource_data(&b"pixels"[..]).unwrap();
+    writer.finish().unwrap();
+    assert!(plain.into_inner().len() > data.len());
+
+    assert_eq!(write_lossless(&psb), data);
+}
+
+#[test]
+fn lossless_unmodified_nan() {
+    let value = object([
+        ("double", PsbValue::Number(PsbNumber::Double(f64::NAN))),
+        ("float", PsbValue::Number(PsbNumber::Float(f32::NAN))),
+        ("frames", PsbValue::List(vec![int(1); 2])),
+    ]);
+    let data = write(3, &value);
+    let mut psb = read_lossless(&data);
+    assert!(!psb.is_modified());
+    assert_eq!(write_lossless(&psb), data);
+
+    let PsbValue::Object(ref mut root) = psb.root else {
+        unreachable!()
+    };
+    root.insert("double".into(), PsbValue::Number(PsbNumber::Double(1.5)));
+    assert!(psb.is_modified());
+}
+
+#[test]
+fn lossless_keeps_integer_width() {
+    let mut buf = Cursor::new(Vec::new());
+    PsbWriter::new(2, false, &int(1), &mut buf)
+        .unwrap()
+        .finish()
+        .unwrap();
+    let data = widen_root_integer(buf.into_inner());
+    assert_eq!(
+        read(data.clone()).deserialize_root::<PsbValue>().unwrap(),
+        int(1)
+    );
+
+    let psb = read_lossless(&data);
+    assert_eq!(write_lossless(&psb), data);
+}
+
+#[test]
+fn lossless_modified_leaf() {
+    let data = write(4, &sample());
+    let mut psb = read_lossless(&data);
+    let PsbValue::Object(ref mut root) = psb.root else {
+        unreachable!()
+    };
+    let Some(PsbValue::List(frames)) = root.get_mut("frames") else {
+        unreachable!()
+    };
+    frames[2] = object([("x", int(2)), ("y", int(-300))]);
+    assert!(psb.is_modified());
+    let expected = psb.root.clone();
+
+    let out = write_lossless(&psb);
+    // The header and the name table before the value tree are unchanged.
+    let entrypoint = u32::from_le_bytes(data[36..40].try_into().unwrap()) as usize;
+    assert_eq!(out[56..entrypoint], data[56..entrypoint]);
+
+    let len = out.len();
+    let mut file = read(out);
+    assert_eq!(file.deserialize_root::<PsbValue>().unwrap(), expected);
+
+    let mut pixels = vec![];
+    file.open_resource(0)
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut pixels)
+        .unwrap();
+    assert_eq!(pixels, b"pixels");
+    let mut extra = vec![];
+    file.open_extra_resource(0)
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut extra)
+        .unwrap();
+    assert_eq!(extra, b"extra");
+
+    // Unmodified frames still share their data.
+    let mut plain = Cursor::new(Vec::new());
+    let mut writer = PsbWriter::new(4, false, &expected, &mut plain).unwrap();
+    writer.add_resource_data(&b"pixels"[..]).unwrap();
+    writer.add_extra_data(&b"extra"[..]).unwrap();
+    writer.finish().unwrap();
+    assert!(plain.into_inner().len() > len);
+}
+
+#[test]
+fn lossless_new_string() {
+    let data = write(3, &sample());
+    let mut psb = read_lossless(&data);
+    let PsbValue::Object(ref mut root) = psb.root else {
+        unreachable!()
+    };
+    root.insert("name".into(), PsbValue::String("another".into()));
+    let expected = psb.root.clone();
+
+    let out = write_lossless(&psb);
+    assert_eq!(read(out).deserialize_root::<PsbValue>().unwrap(), expected);
+}
+
+#[test]
+fn lossless_new_string_after_duplicates() {
+    let root = PsbValue::List(vec![
+        PsbValue::String("same".into()),
+        PsbValue::String("othr".into()),
+    ]);
+    let mut buf = Cursor::new(Vec::new());
+    PsbWriter::new(3, false, &root, &mut buf)
+        .unwrap()
+        .finish()
+        .unwrap();
+    // Rename the string data in place so the string table holds "same" twice.
+    let mut data = buf.into_inner();
+    let position = data.windows(5).position(|w| w == b"othr\0").unwrap();
+    data[position..position + 4].copy_from_slice(b"same");
+
+    let mut psb = read_lossless(&data);
+    let PsbValue::List(ref mut items) = psb.root else {
+        unreachable!()
+    };
+    items.push(PsbValue::String("new".into()));
+    let expected = PsbValue::List(vec![
+        PsbValue::String("same".into()),
+        PsbValue::String("same".into()),
+        PsbValue::String("new".into()),
+    ]);
+    assert_eq!(psb.root, expected);
+
+    let out = write_lossless(&psb);
+    assert_eq!(read(out).deserialize_root::<PsbValue>().unwrap(), expected);
+}
+
+#[test]
+fn lossless_unknown_name() {
+    let data = write(3, &sample());
+    let mut psb = read_lossless(&data);
+    let PsbValue::Object(ref mut root) = psb.root else {
+        unreachable!()
+    };
+    root.insert("unknown".into(), PsbValue::Null);
+
+    assert!(matches!(
+        psb.write(&mut vec![]),
+        Err(PsbWriteError::UnknownName(name)) if name == "unknown"
+    ));
+}
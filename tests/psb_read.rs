@@ -0,0 +1,143 @@
+use std::io::Cursor;
+
+use emote_psb::{
+    psb::{
+        read::PsbFile,
+        table::StringTable,
+        write::{BtreePacking, PsbWriter, PsbWriterOptions},
+    },
+    value::{
+        PsbBytes, PsbValue,
+        de::{self, Deserializer},
+    },
+};
+use indexmap::IndexMap;
+use serde::Deserialize;
+use smol_str::SmolStr;
+
+const NAMES: &[&str] = &["", "a", "ab", "abc", "abd", "b", "body", "bone", "日本"];
+
+fn names_file(packing: BtreePacking) -> PsbFile<Cursor<Vec<u8>>> {
+    let root = PsbValue::Object(
+        NAMES
+            .iter()
+            .map(|&name| (SmolStr::new(name), PsbValue::Null))
+            .collect::<IndexMap<_, _>>(),
+    );
+
+    let mut buf = Cursor::new(Vec::new());
+    let options = PsbWriterOptions::new(3).btree_packing(packing);
+    PsbWriter::with_options(&options, &root, &mut buf)
+        .unwrap()
+        .finish()
+        .unwrap();
+    PsbFile::open(Cursor::new(buf.into_inner())).unwrap()
+}
+
+#[test]
+fn name_id_lookup() {
+    for packing in [BtreePacking::Sequential, BtreePacking::FirstFit] {
+        let psb = names_file(packing);
+        for (id, name) in psb.names.iter().enumerate() {
+            assert_eq!(psb.name_id(name), Some(id), "{name:?}");
+        }
+
+        for missing in ["c", "abcd", "bo", "日", "\0", "a\0"] {
+            assert_eq!(psb.name_id(missing), None, "{missing:?}");
+        }
+    }
+}
+
+#[test]
+fn names_with_prefix() {
+    for packing in [BtreePacking::Sequential, BtreePacking::FirstFit] {
+        let psb = names_file(packing);
+        let prefixed = |prefix| {
+            psb.names_with_prefix(prefix)
+                .map(|(id, name)| {
+                    assert_eq!(psb.names.get(id), Some(name));
+                    name.to_owned()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(prefixed("ab"), ["ab", "abc", "abd"]);
+        assert_eq!(prefixed("bo"), ["body", "bone"]);
+        assert_eq!(prefixed("日"), ["日本"]);
+        assert!(prefixed("c").is_empty());
+        assert_eq!(prefixed(""), NAMES);
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct Model {
+    name: String,
+    image: Vec<u8>,
+    sound: Option<Vec<u8>>,
+    frames: Vec<Vec<u8>>,
+    extra: PsbBytes,
+}
+
+fn model_value() -> PsbValue {
+    PsbValue::Object(IndexMap::from([
+        ("name".into(), PsbValue::String("model".into())),
+        ("image".into(), PsbValue::Resource(1)),
+        ("sound".into(), PsbValue::ExtraResource(0)),
+        (
+            "frames".into(),
+            PsbValue::List(vec![PsbValue::Resource(0), PsbValue::Resource(1)]),
+        ),
+        ("extra".into(), PsbValue::ExtraResource(1)),
+    ]))
+}
+
+#[test]
+fn resolved_resources() {
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = PsbWriter::new(4, false, &model_value(), &mut buf).unwrap();
+    for data in [b"frame", b"image"] {
+        writer.add_resource_data(&data[..]).unwrap();
+    }
+    for data in [b"sound", b"extra"] {
+        writer.add_extra_data(&data[..]).unwrap();
+    }
+    writer.finish().unwrap();
+
+    let mut psb = PsbFile::open(Cursor::new(buf.into_inner())).unwrap();
+    assert_eq!(
+        psb.deserialize_root_with_resources::<Model>().unwrap(),
+        Model {
+            name: "model".into(),
+            image: b"image".to_vec(),
+            sound: Some(b"sound".to_vec()),
+            frames: vec![b"frame".to_vec(), b"image".to_vec()],
+            extra: PsbBytes(b"extra".to_vec()),
+        }
+    );
+
+    // Without resolving, byte fields do not accept resource references
+    assert!(psb.deserialize_root::<Model>().is_err());
+    assert_eq!(
+        psb.deserialize_root_with_resources::<PsbValue>().unwrap(),
+        model_value()
+    );
+}
+
+#[test]
+fn resolved_resources_missing() {
+    let names = StringTable::new();
+    let strings = StringTable::new();
+    let read = |data: &[u8]| {
+        let mut de = Deserializer::new(&names, &strings, Cursor::new(data)).resolve_resources(true);
+        Vec::<u8>::deserialize(&mut de)
+    };
+
+    assert!(matches!(
+        read(&[0x19, 3]),
+        Err(de::Error::MissingResource(3))
+    ));
+    assert!(matches!(
+        read(&[0x22, 1]),
+        Err(de::Error::MissingExtraResource(1))
+    ));
+}
@@ -1,16 +1,18 @@
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::io::Cursor;
 
 use emote_psb::{
     psb::{read::PsbFile, table::StringTable, write::PsbWriter},
     value::{
-        PsbValue,
-        de::Deserializer,
+        PsbIntArray, PsbValue,
+        de::{self, Deserializer},
         number::PsbNumber,
         ser::{Buffer, serialize},
     },
 };
-use serde::Deserialize;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize, Serializer, de::DeserializeOwned};
 use smol_str::SmolStr;
 
 /// Performs a full PSB file round-trip: serialize with `PsbWriter`, then
@@ -24,6 +26,23 @@ fn psb_roundtrip(value: &PsbValue) -> PsbValue {
     psb.deserialize_root::<PsbValue>().unwrap()
 }
 
+/// Performs a full PSB file round-trip like [`psb_roundtrip`], with `resources`
+/// and `extra` empty resources attached to a version 4 file.
+fn psb_roundtrip_with_resources(value: &PsbValue, resources: usize, extra: usize) -> PsbValue {
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = PsbWriter::new(4, false, value, &mut buf).unwrap();
+    for _ in 0..resources {
+        writer.add_resource_data(&[][..]).unwrap();
+    }
+    for _ in 0..extra {
+        writer.add_extra_data(&[][..]).unwrap();
+    }
+    writer.finish().unwrap();
+    buf.set_position(0);
+    let mut psb = PsbFile::open(buf).unwrap();
+    psb.deserialize_root::<PsbValue>().unwrap()
+}
+
 /// Performs a serde-level round-trip: serialize with `value::ser::serialize`,
 /// then deserialize with `value::de::Deserializer` — without writing a full
 /// PSB file header.
@@ -116,13 +135,13 @@ fn psb_string_roundtrip() {
 #[test]
 fn psb_resource_roundtrip() {
     let val = PsbValue::Resource(42);
-    assert_eq!(psb_roundtrip(&val), val);
+    assert_eq!(psb_roundtrip_with_resources(&val, 43, 0), val);
 }
 
 #[test]
 fn psb_extra_resource_roundtrip() {
     let val = PsbValue::ExtraResource(7);
-    assert_eq!(psb_roundtrip(&val), val);
+    assert_eq!(psb_roundtrip_with_resources(&val, 0, 8), val);
 }
 
 #[test]
@@ -150,7 +169,7 @@ fn psb_nested_list_roundtrip() {
 
 #[test]
 fn psb_object_roundtrip() {
-    let mut map = HashMap::new();
+    let mut map = IndexMap::new();
     map.insert(
         SmolStr::new("alpha"),
         PsbValue::Number(PsbNumber::Integer(1)),
@@ -163,11 +182,11 @@ fn psb_object_roundtrip() {
 
 #[test]
 fn psb_nested_object_roundtrip() {
-    let mut inner = HashMap::new();
+    let mut inner = IndexMap::new();
     inner.insert(SmolStr::new("x"), PsbValue::Number(PsbNumber::Integer(10)));
     inner.insert(SmolStr::new("y"), PsbValue::Number(PsbNumber::Integer(20)));
 
-    let mut outer = HashMap::new();
+    let mut outer = IndexMap::new();
     outer.insert(SmolStr::new("nested"), PsbValue::Object(inner));
     outer.insert(
         SmolStr::new("count"),
@@ -313,7 +332,7 @@ fn serde_list() {
 
 #[test]
 fn serde_object() {
-    let mut map = HashMap::new();
+    let mut map = IndexMap::new();
     map.insert(
         SmolStr::new("key1"),
         PsbValue::Number(PsbNumber::Integer(1)),
@@ -375,7 +394,7 @@ fn serde_compiler_binary_tree() {
 /// After serialization, the names table must be sorted in alphabetical order.
 #[test]
 fn object_keys_sorted_in_names_table() {
-    let mut map = HashMap::new();
+    let mut map = IndexMap::new();
     map.insert(SmolStr::new("zebra"), PsbValue::Null);
     map.insert(SmolStr::new("apple"), PsbValue::Null);
     map.insert(SmolStr::new("mango"), PsbValue::Null);
@@ -394,11 +413,11 @@ fn object_keys_sorted_in_names_table() {
 /// Nested object keys from all levels must all be sorted alphabetically.
 #[test]
 fn nested_object_keys_sorted_in_names_table() {
-    let mut inner = HashMap::new();
+    let mut inner = IndexMap::new();
     inner.insert(SmolStr::new("zeta"), PsbValue::Null);
     inner.insert(SmolStr::new("alpha"), PsbValue::Null);
 
-    let mut outer = HashMap::new();
+    let mut outer = IndexMap::new();
     outer.insert(SmolStr::new("outer_b"), PsbValue::Object(inner));
     outer.insert(SmolStr::new("outer_a"), PsbValue::Null);
 
@@ -419,7 +438,7 @@ fn nested_object_keys_sorted_in_names_table() {
 /// correctly after a full PSB round-trip.
 #[test]
 fn object_keys_all_preserved_after_roundtrip() {
-    let mut map = HashMap::new();
+    let mut map = IndexMap::new();
     map.insert(
         SmolStr::new("zebra"),
         PsbValue::Number(PsbNumber::Integer(3)),
@@ -449,12 +468,58 @@ fn object_keys_all_preserved_after_roundtrip() {
     }
 }
 
+/// Objects read back keep the on-disk order of their keys, which is sorted by name.
+#[test]
+fn object_keys_file_order() {
+    let map = IndexMap::from([
+        (SmolStr::new("zebra"), PsbValue::Null),
+        (SmolStr::new("apple"), PsbValue::Bool(true)),
+        (SmolStr::new("mango"), PsbValue::Bool(false)),
+    ]);
+
+    let val = PsbValue::Object(map);
+    let PsbValue::Object(ref map) = val else {
+        unreachable!();
+    };
+    assert!(map.keys().eq(["zebra", "apple", "mango"]));
+
+    for result in [psb_roundtrip(&val), serde_roundtrip(&val)] {
+        let PsbValue::Object(result_map) = result else {
+            panic!("expected Object variant after round-trip");
+        };
+        assert!(result_map.keys().eq(["apple", "mango", "zebra"]));
+    }
+}
+
+/// The insertion order of keys does not change the serialized output.
+#[test]
+fn object_insertion_order_not_serialized() {
+    let entries = [
+        (SmolStr::new("b"), PsbValue::Bool(true)),
+        (SmolStr::new("a"), PsbValue::Null),
+        (SmolStr::new("c"), PsbValue::Bool(false)),
+    ];
+    let write = |entries: &[(SmolStr, PsbValue)]| {
+        let value = PsbValue::Object(entries.iter().cloned().collect());
+        let mut buf = Cursor::new(Vec::new());
+        PsbWriter::new(2, false, &value, &mut buf)
+            .unwrap()
+            .finish()
+            .unwrap();
+        buf.into_inner()
+    };
+
+    let mut reversed = entries.clone();
+    reversed.reverse();
+    assert_eq!(write(&entries), write(&reversed));
+}
+
 /// Ensure that a PSB object with many keys round-trips correctly and that the
 /// serialized names table is still alphabetically sorted.
 #[test]
 fn object_many_keys_sorted_and_preserved() {
     let keys = ["omega", "beta", "delta", "alpha", "gamma", "epsilon"];
-    let mut map = HashMap::new();
+    let mut map = IndexMap::new();
     for (i, k) in keys.iter().enumerate() {
         map.insert(
             SmolStr::new(*k),
@@ -485,3 +550,233 @@ fn object_many_keys_sorted_and_preserved() {
         );
     }
 }
+
+/// Yields its entries only once, like a value backed by a consumed iterator.
+struct OneShot(Cell<Option<Vec<(&'static str, &'static str)>>>);
+
+impl Serialize for OneShot {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.take().unwrap_or_default())
+    }
+}
+
+#[test]
+fn psb_one_shot_serialize() {
+    let value = OneShot(Cell::new(Some(vec![("b", "second"), ("a", "first")])));
+    let mut buf = Cursor::new(Vec::new());
+    PsbWriter::new(2, false, &value, &mut buf)
+        .unwrap()
+        .finish()
+        .unwrap();
+    buf.set_position(0);
+
+    let mut psb = PsbFile::open(buf).unwrap();
+    let PsbValue::Object(map) = psb.deserialize_root::<PsbValue>().unwrap() else {
+        panic!("expected Object variant");
+    };
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.get("a"), Some(&PsbValue::String("first".into())));
+    assert_eq!(map.get("b"), Some(&PsbValue::String("second".into())));
+}
+
+// ---------------------------------------------------------------------------
+// Integer array tests
+// ---------------------------------------------------------------------------
+
+#[test]
+fn psb_int_array_roundtrip() {
+    for items in [vec![], vec![0, 1, 255], vec![256, 0x1_0000_0000, u64::MAX]] {
+        let val = PsbValue::IntArray(items);
+        assert_eq!(psb_roundtrip(&val), val);
+        assert_eq!(serde_roundtrip(&val), val);
+    }
+}
+
+#[test]
+fn int_array_item_width() {
+    let mut buf = Buffer::new();
+    serialize(&PsbIntArray(vec![1u16, 0x1234]), &mut buf).unwrap();
+    let mut bytes = vec![];
+    buf.write(&mut bytes).unwrap();
+    assert_eq!(bytes, [0x0D, 2, 0x0E, 0x01, 0x00, 0x34, 0x12]);
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Frames {
+    packed: PsbIntArray<u16>,
+    plain: Vec<u32>,
+}
+
+#[test]
+fn int_array_typed_roundtrip() {
+    let value = Frames {
+        packed: PsbIntArray(vec![1, 2, 0xffff]),
+        plain: vec![4, 5],
+    };
+    assert_eq!(typed_roundtrip(&value), value);
+
+    let PsbValue::Object(map) = as_psb_value(&value) else {
+        panic!("expected Object variant");
+    };
+    assert_eq!(
+        map.get("packed"),
+        Some(&PsbValue::IntArray(vec![1, 2, 0xffff]))
+    );
+}
+
+#[test]
+fn int_array_as_sequence() {
+    let value = PsbIntArray(vec![7u8, 8, 9]);
+    assert_eq!(reencode::<Vec<u32>>(&value).unwrap(), [7, 8, 9]);
+    assert_eq!(reencode::<(u8, u8, u8)>(&value).unwrap(), (7, 8, 9));
+    assert!(reencode::<PsbIntArray<u8>>(&PsbIntArray(vec![256u16])).is_err());
+}
+
+#[test]
+fn int_array_from_sequence() {
+    assert_eq!(
+        reencode::<PsbIntArray<u32>>(&vec![1u32, 2, 0x1_0000]).unwrap(),
+        PsbIntArray(vec![1, 2, 0x1_0000])
+    );
+    assert!(reencode::<PsbIntArray<u32>>(&vec!["a"]).is_err());
+
+    #[derive(Serialize)]
+    struct PlainFrames {
+        packed: Vec<u16>,
+        plain: Vec<u32>,
+    }
+    let value = PlainFrames {
+        packed: vec![1, 2],
+        plain: vec![4, 5],
+    };
+    assert_eq!(
+        reencode::<Frames>(&value).unwrap(),
+        Frames {
+            packed: PsbIntArray(vec![1, 2]),
+            plain: vec![4, 5],
+        }
+    );
+}
+
+// ---------------------------------------------------------------------------
+// Enum representation tests
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+enum Shape {
+    Empty,
+    Circle(u32),
+    Line(i32, i32),
+    Rect { width: u32, height: u32 },
+}
+
+/// Writes `value` as a version 2 PSB file and reads its root back as `T`.
+fn reencode<T: DeserializeOwned>(value: &impl Serialize) -> Result<T, de::Error> {
+    let mut buf = Cursor::new(Vec::new());
+    PsbWriter::new(2, false, value, &mut buf)
+        .unwrap()
+        .finish()
+        .unwrap();
+    buf.set_position(0);
+    PsbFile::open(buf).unwrap().deserialize_root()
+}
+
+/// Performs a full PSB file round-trip of any serializable type.
+fn typed_roundtrip<T: Serialize + DeserializeOwned>(value: &T) -> T {
+    reencode(value).unwrap()
+}
+
+/// Reads back a serialized value as a [`PsbValue`].
+fn as_psb_value(value: &impl Serialize) -> PsbValue {
+    reencode(value).unwrap()
+}
+
+#[test]
+fn enum_unit_variant_as_string() {
+    assert_eq!(
+        as_psb_value(&Shape::Empty),
+        PsbValue::String("Empty".into())
+    );
+    assert_eq!(typed_roundtrip(&Shape::Empty), Shape::Empty);
+}
+
+#[test]
+fn enum_newtype_variant_as_object() {
+    let PsbValue::Object(map) = as_psb_value(&Shape::Circle(3)) else {
+        panic!("expected Object variant");
+    };
+    assert_eq!(map.len(), 1);
+    assert_eq!(
+        map.get("Circle"),
+        Some(&PsbValue::Number(PsbNumber::Integer(3)))
+    );
+    assert_eq!(typed_roundtrip(&Shape::Circle(3)), Shape::Circle(3));
+}
+
+#[test]
+fn enum_tuple_variant_roundtrip() {
+    let PsbValue::Object(map) = as_psb_value(&Shape::Line(-1, 2)) else {
+        panic!("expected Object variant");
+    };
+    assert_eq!(
+        map.get("Line"),
+        Some(&PsbValue::List(vec![
+            PsbValue::Number(PsbNumber::Integer(-1)),
+            PsbValue::Number(PsbNumber::Integer(2)),
+        ]))
+    );
+    assert_eq!(typed_roundtrip(&Shape::Line(-1, 2)), Shape::Line(-1, 2));
+}
+
+#[test]
+fn enum_struct_variant_roundtrip() {
+    let value = Shape::Rect {
+        width: 4,
+        height: 5,
+    };
+    let PsbValue::Object(map) = as_psb_value(&value) else {
+        panic!("expected Object variant");
+    };
+    let Some(PsbValue::Object(fields)) = map.get("Rect") else {
+        panic!("expected Rect object");
+    };
+    assert_eq!(fields.len(), 2);
+    assert_eq!(typed_roundtrip(&value), value);
+}
+
+#[test]
+fn enum_nested_roundtrip() {
+    let value = (
+        vec![
+            Shape::Empty,
+            Shape::Circle(1),
+            Shape::Line(2, 3),
+            Shape::Rect {
+                width: 4,
+                height: 5,
+            },
+        ],
+        Some(Shape::Circle(6)),
+        None::<Shape>,
+    );
+    assert_eq!(typed_roundtrip(&value), value);
+}
+
+#[test]
+fn enum_unit_variant_as_key() {
+    let value = HashMap::from([(Shape::Empty, 1), (Shape::Circle(2), 2)]);
+    assert!(
+        PsbWriter::new(2, false, &value, &mut Cursor::new(Vec::new())).is_err(),
+        "only unit variants can be object keys"
+    );
+
+    let value = HashMap::from([(Shape::Empty, 1)]);
+    assert_eq!(typed_roundtrip(&value), value);
+}
+
+#[test]
+fn enum_invalid_representation() {
+    assert!(reencode::<Shape>(&HashMap::from([("Empty", 1), ("Circle", 2)])).is_err());
+    assert!(reencode::<Shape>(&"Unknown").is_err());
+    assert!(reencode::<Shape>(&1).is_err());
+}
@@ -0,0 +1,1127 @@
+mod common;
+
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+
+use emote_psb::{
+    psb::{
+        error::PsbWriteError,
+        read::PsbFile,
+        write::{
+            BtreePacking, ExtraPlacement, PsbWriter, PsbWriterOptions, ResourceDedup,
+            UnusedResources, estimate_size, estimate_size_with_options,
+        },
+    },
+    value::{
+        PsbBytes, PsbValue,
+        number::PsbNumber,
+        ser::{self, Buffer, serialize},
+    },
+};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use smol_str::SmolStr;
+use twox_hash::XxHash3_64;
+
+use common::{Tree, write_psb, write_with};
+
+fn read_psb(data: Vec<u8>) -> PsbValue {
+    let mut psb = PsbFile::open(Cursor::new(data)).unwrap();
+    psb.deserialize_root::<PsbValue>().unwrap()
+}
+
+fn point(x: i64, y: i64) -> PsbValue {
+    let mut map = IndexMap::new();
+    map.insert(SmolStr::new("x"), PsbValue::Number(PsbNumber::Integer(x)));
+    map.insert(SmolStr::new("y"), PsbValue::Number(PsbNumber::Integer(y)));
+    PsbValue::Object(map)
+}
+
+/// A motion-like tree with many repeated coordinates and frame lists.
+fn repetitive_tree() -> PsbValue {
+    let frames = PsbValue::List((0..16).map(|i| point(i % 4, 100)).collect());
+
+    let mut root = IndexMap::new();
+    root.insert(
+        SmolStr::new("points"),
+        PsbValue::List((0..64).map(|i| point(i % 3, -5)).collect()),
+    );
+    root.insert(
+        SmolStr::new("layers"),
+        PsbValue::List(vec![frames.clone(), frames.clone(), frames]),
+    );
+    root.insert(
+        SmolStr::new("flags"),
+        PsbValue::List(vec![PsbValue::Bool(true); 32]),
+    );
+    PsbValue::Object(root)
+}
+
+#[test]
+fn dedup_roundtrip() {
+    let value = repetitive_tree();
+    let data = write_psb(&PsbWriterOptions::new(3).dedup(true), &value, &[], &[]);
+    assert_eq!(read_psb(data), value);
+}
+
+#[test]
+fn dedup_output_smaller() {
+    let value = repetitive_tree();
+    let plain = write_psb(&PsbWriterOptions::new(3), &value, &[], &[]);
+    let dedup = write_psb(&PsbWriterOptions::new(3).dedup(true), &value, &[], &[]);
+    assert!(
+        dedup.len() < plain.len(),
+        "deduplicated output ({}) is not smaller than plain output ({})",
+        dedup.len(),
+        plain.len()
+    );
+    assert_eq!(read_psb(dedup), read_psb(plain));
+}
+
+#[test]
+fn dedup_distinct_values_unchanged() {
+    let value = PsbValue::List(
+        (0..32)
+            .map(|i| PsbValue::List(vec![PsbValue::Number(PsbNumber::Integer(i))]))
+            .collect(),
+    );
+    let plain = write_psb(&PsbWriterOptions::new(3), &value, &[], &[]);
+    let dedup = write_psb(&PsbWriterOptions::new(3).dedup(true), &value, &[], &[]);
+    assert_eq!(dedup, plain);
+}
+
+#[test]
+fn dedup_similar_values_not_merged() {
+    // Same encoded length but different content must not be shared.
+    let value = PsbValue::List(vec![
+        PsbValue::Number(PsbNumber::Integer(1)),
+        PsbValue::Number(PsbNumber::Integer(2)),
+        PsbValue::Number(PsbNumber::Integer(1)),
+        PsbValue::List(vec![PsbValue::Null]),
+        PsbValue::List(vec![PsbValue::Bool(false)]),
+        PsbValue::List(vec![PsbValue::Null]),
+    ]);
+    let data = write_psb(&PsbWriterOptions::new(2).dedup(true), &value, &[], &[]);
+    assert_eq!(read_psb(data), value);
+}
+
+fn read_resource(data: &[u8], index: usize) -> Vec<u8> {
+    let mut psb = PsbFile::open(Cursor::new(data)).unwrap();
+    let mut out = vec![];
+    psb.open_resource(index)
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut out)
+        .unwrap();
+    out
+}
+
+#[test]
+fn resource_hash() {
+    let mut buf = Cursor::new(Vec::new());
+    let options = PsbWriterOptions::new(4).resource_hashing(true);
+    let mut writer = PsbWriter::with_options(&options, &PsbValue::Null, &mut buf).unwrap();
+    writer.add_resource(Cursor::new(b"abc".to_vec())).unwrap();
+    writer.add_resource(Cursor::new(b"abd".to_vec())).unwrap();
+    writer.add_extra(Cursor::new(b"abc".to_vec())).unwrap();
+
+    assert!(writer.resource_hash(0).is_some());
+    assert_ne!(writer.resource_hash(0), writer.resource_hash(1));
+    assert_eq!(writer.resource_hash(0), writer.extra_resource_hash(0));
+    assert_eq!(writer.resource_hash(2), None);
+}
+
+#[test]
+fn resource_hash_disabled() {
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = PsbWriter::new(3, false, &PsbValue::Null, &mut buf).unwrap();
+    writer.add_resource(Cursor::new(b"abc".to_vec())).unwrap();
+    assert_eq!(writer.resource_hash(0), None);
+}
+
+#[test]
+fn resource_dedup_reuse_index() {
+    let resources: [&[u8]; 4] = [b"image", b"sound", b"image", b"imagf"];
+    let options = PsbWriterOptions::new(3).resource_dedup(ResourceDedup::ReuseIndex);
+    let (data, indices) = write_with(&options, &PsbValue::Null, &resources, &[]).unwrap();
+    assert_eq!(indices, [0, 1, 0, 2]);
+
+    let (plain, _) =
+        write_with(&PsbWriterOptions::new(3), &PsbValue::Null, &resources, &[]).unwrap();
+    assert!(data.len() < plain.len());
+
+    for (res, index) in resources.iter().zip(indices) {
+        assert_eq!(read_resource(&data, index), *res);
+    }
+}
+
+#[test]
+fn resource_dedup_share_data() {
+    let resources: [&[u8]; 4] = [b"image", b"sound", b"image", b"imagf"];
+    let options = PsbWriterOptions::new(3).resource_dedup(ResourceDedup::ShareData);
+    let (data, indices) = write_with(&options, &PsbValue::Null, &resources, &[]).unwrap();
+    assert_eq!(indices, [0, 1, 2, 3]);
+
+    let (plain, _) =
+        write_with(&PsbWriterOptions::new(3), &PsbValue::Null, &resources, &[]).unwrap();
+    assert_eq!(data.len() + resources[2].len(), plain.len());
+
+    for (res, index) in resources.iter().zip(indices) {
+        assert_eq!(read_resource(&data, index), *res);
+    }
+}
+
+#[test]
+fn borrowed_and_owned_resources() {
+    let borrowed = b"borrowed".to_vec();
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = PsbWriter::new(4, false, &PsbValue::Null, &mut buf).unwrap();
+    assert_eq!(writer.add_resource_data(&borrowed[..]).unwrap(), 0);
+    assert_eq!(writer.add_resource_data(b"owned".to_vec()).unwrap(), 1);
+    assert_eq!(writer.add_extra_data(&borrowed[..]).unwrap(), 0);
+    writer.finish().unwrap();
+
+    let data = buf.into_inner();
+    assert_eq!(read_resource(&data, 0), b"borrowed");
+    assert_eq!(read_resource(&data, 1), b"owned");
+
+    let mut psb = PsbFile::open(Cursor::new(&data)).unwrap();
+    let mut extra = vec![];
+    psb.open_extra_resource(0)
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut extra)
+        .unwrap();
+    assert_eq!(extra, b"borrowed");
+}
+
+#[test]
+fn produced_resources() {
+    let texture = vec![7_u8; 300];
+    let mut buf = Cursor::new(Vec::new());
+    let options = PsbWriterOptions::new(3).resource_hashing(true);
+    let mut writer = PsbWriter::with_options(&options, &PsbValue::Null, &mut buf).unwrap();
+    writer.add_resource_data(&b"head"[..]).unwrap();
+    let index = writer
+        .add_resource_with(texture.len() as u64, |out| {
+            for chunk in texture.chunks(64) {
+                out.write_all(chunk)?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    writer.add_resource_data(&b"tail"[..]).unwrap();
+    assert_eq!(writer.resource_hash(index), None);
+    writer.finish().unwrap();
+
+    let data = buf.into_inner();
+    assert_eq!(read_resource(&data, 0), b"head");
+    assert_eq!(read_resource(&data, index), texture);
+    assert_eq!(read_resource(&data, 2), b"tail");
+}
+
+#[test]
+fn produced_resource_length_mismatch() {
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = PsbWriter::new(3, false, &PsbValue::Null, &mut buf).unwrap();
+    writer
+        .add_resource_with(4, |out| out.write_all(b"abc"))
+        .unwrap();
+    assert!(writer.finish().is_err());
+}
+
+#[test]
+fn extra_resources_unsupported() {
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = PsbWriter::new(3, false, &PsbValue::Null, &mut buf).unwrap();
+    assert!(matches!(
+        writer.add_extra(Cursor::new(b"extra")),
+        Err(PsbWriteError::ExtraResourcesUnsupported(3))
+    ));
+    assert!(matches!(
+        writer.add_extra_data(&b"extra"[..]),
+        Err(PsbWriteError::ExtraResourcesUnsupported(3))
+    ));
+    assert!(matches!(
+        writer.add_extra_with(0, |_| Ok(())),
+        Err(PsbWriteError::ExtraResourcesUnsupported(3))
+    ));
+    writer.finish().unwrap();
+}
+
+#[test]
+fn missing_resources() {
+    let value = PsbValue::List(vec![PsbValue::Resource(0), PsbValue::Resource(2)]);
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = PsbWriter::new(4, false, &value, &mut buf).unwrap();
+    writer.add_resource_data(&b"a"[..]).unwrap();
+    writer.add_resource_data(&b"b"[..]).unwrap();
+    assert!(matches!(
+        writer.finish(),
+        Err(PsbWriteError::MissingResource(2))
+    ));
+
+    let value = PsbValue::ExtraResource(0);
+    let mut buf = Cursor::new(Vec::new());
+    let writer = PsbWriter::new(4, false, &value, &mut buf).unwrap();
+    assert!(matches!(
+        writer.finish(),
+        Err(PsbWriteError::MissingExtraResource(0))
+    ));
+}
+
+fn read_extra_resource(data: &[u8], index: usize) -> Vec<u8> {
+    let mut psb = PsbFile::open(Cursor::new(data)).unwrap();
+    let mut out = vec![];
+    psb.open_extra_resource(index)
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut out)
+        .unwrap();
+    out
+}
+
+#[test]
+fn unused_resources_denied() {
+    let value = PsbValue::List(vec![PsbValue::Resource(0), PsbValue::Resource(2)]);
+    let options = PsbWriterOptions::new(4).unused_resources(UnusedResources::Deny);
+    let result = write_with(&options, &value, &[b"a", b"b", b"c"], &[b"x"]);
+    assert!(matches!(
+        result,
+        Err(PsbWriteError::UnusedResources { resources, extra })
+            if resources == [1] && extra == [0]
+    ));
+
+    let options = PsbWriterOptions::new(4).unused_resources(UnusedResources::Keep);
+    let data = write_psb(&options, &value, &[b"a", b"b", b"c"], &[b"x"]);
+    assert_eq!(read_resource(&data, 1), b"b");
+}
+
+#[test]
+fn unused_resources_removed() {
+    let value = PsbValue::List(vec![
+        PsbValue::Resource(2),
+        PsbValue::Resource(0),
+        PsbValue::ExtraResource(1),
+        PsbValue::Resource(2),
+    ]);
+    for dedup in [false, true] {
+        let options = PsbWriterOptions::new(4)
+            .dedup(dedup)
+            .unused_resources(UnusedResources::Remove);
+        let data = write_psb(&options, &value, &[b"a", b"b", b"c"], &[b"x", b"y"]);
+
+        assert_eq!(
+            read_psb(data.clone()),
+            PsbValue::List(vec![
+                PsbValue::Resource(1),
+                PsbValue::Resource(0),
+                PsbValue::ExtraResource(0),
+                PsbValue::Resource(1),
+            ])
+        );
+        let psb = PsbFile::open(Cursor::new(&data[..])).unwrap();
+        assert_eq!(psb.resources(), 2);
+        assert_eq!(psb.extra_resources(), 1);
+        assert_eq!(read_resource(&data, 0), b"a");
+        assert_eq!(read_resource(&data, 1), b"c");
+        assert_eq!(read_extra_resource(&data, 0), b"y");
+    }
+}
+
+#[test]
+fn unused_resources_removed_shared_data() {
+    let value = PsbValue::Resource(2);
+    let options = PsbWriterOptions::new(3)
+        .resource_dedup(ResourceDedup::ShareData)
+        .unused_resources(UnusedResources::Remove);
+    let data = write_psb(&options, &value, &[b"data", b"b", b"data"], &[]);
+
+    assert_eq!(read_psb(data.clone()), PsbValue::Resource(0));
+    assert_eq!(read_resource(&data, 0), b"data");
+    // Only the shared data is left.
+    let single = write_psb(
+        &PsbWriterOptions::new(3),
+        &PsbValue::Resource(0),
+        &[b"data"],
+        &[],
+    );
+    assert_eq!(data.len(), single.len());
+}
+
+#[test]
+fn compacted_resources_carried_over() {
+    let sprite = |res: u32| {
+        let mut map = IndexMap::new();
+        map.insert(SmolStr::new("pixel"), PsbValue::Resource(res));
+        map.insert(SmolStr::new("mask"), PsbValue::ExtraResource(res));
+        PsbValue::Object(map)
+    };
+    let options = PsbWriterOptions::new(4);
+    let tree = PsbValue::List((0..4).map(sprite).collect());
+    let resources: [&[u8]; 4] = [b"r0", b"r1", b"r2", b"r3"];
+    let extra: [&[u8]; 4] = [b"e0", b"e1", b"e2", b"e3"];
+    let data = write_psb(&options, &tree, &resources, &extra);
+
+    let mut value = read_psb(data.clone());
+    let PsbValue::List(ref mut sprites) = value else {
+        unreachable!()
+    };
+    sprites.remove(2);
+    sprites.remove(0);
+
+    let remap = value.compact_resources();
+    assert_eq!(remap.resources(), [1, 3]);
+    assert_eq!(remap.extra_resources(), [1, 3]);
+    assert_eq!(remap.resource(3), Some(1));
+    assert_eq!(remap.resource(2), None);
+    assert_eq!(value, PsbValue::List(vec![sprite(0), sprite(1)]));
+
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = PsbWriter::with_options(&options, &value, &mut buf).unwrap();
+    for &index in remap.resources() {
+        writer
+            .add_resource_data(read_resource(&data, index as usize))
+            .unwrap();
+    }
+    for &index in remap.extra_resources() {
+        writer
+            .add_extra_data(read_extra_resource(&data, index as usize))
+            .unwrap();
+    }
+    writer.finish().unwrap();
+
+    let out = buf.into_inner();
+    assert_eq!(read_resource(&out, 1), b"r3");
+    assert_eq!(read_extra_resource(&out, 0), b"e1");
+}
+
+fn header_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn uint_array(data: &[u8], position: u32) -> Vec<u64> {
+    let uint = |data: &[u8]| {
+        let mut bytes = [0; 8];
+        bytes[..data.len()].copy_from_slice(data);
+        u64::from_le_bytes(bytes)
+    };
+
+    let data = &data[position as usize..];
+    let len_n = (data[0] - 0x0c) as usize;
+    let len = uint(&data[1..1 + len_n]) as usize;
+    let n = (data[1 + len_n] - 0x0c) as usize;
+    data[2 + len_n..][..len * n].chunks(n).map(uint).collect()
+}
+
+/// Returns the absolute position of each resource, or extra resource, in `data`.
+fn resource_positions(data: &[u8], extra: bool) -> Vec<u64> {
+    let slot = if extra { 44 } else { 24 };
+    let start = header_u32(data, slot + 8) as u64;
+    uint_array(data, header_u32(data, slot))
+        .into_iter()
+        .map(|offset| start + offset)
+        .collect()
+}
+
+fn assert_checksum(data: &[u8]) {
+    let mut adler = adler2::Adler32::new();
+    adler.write_slice(&data[8..40]);
+    adler.write_slice(&data[44..56]);
+    assert_eq!(header_u32(data, 40), adler.checksum());
+}
+
+const ALIGNED: [&[u8]; 4] = [b"a", b"", b"bcdefghijklmnopqrs", b"t"];
+
+#[test]
+fn resource_alignment() {
+    for alignment in [16, 4096] {
+        let value = PsbValue::List((0..4).map(PsbValue::Resource).collect());
+        let options = PsbWriterOptions::new(4).resource_alignment(alignment);
+        let data = write_psb(&options, &value, &ALIGNED, &[b"x", b"y"]);
+
+        assert_checksum(&data);
+        for extra in [false, true] {
+            for position in resource_positions(&data, extra) {
+                assert_eq!(position % alignment, 0);
+            }
+        }
+        for (i, res) in ALIGNED.iter().enumerate() {
+            assert_eq!(read_resource(&data, i), *res);
+        }
+        assert_eq!(read_extra_resource(&data, 1), b"y");
+    }
+}
+
+#[test]
+fn extra_after_resources() {
+    let options = PsbWriterOptions::new(4).extra_placement(ExtraPlacement::AfterResources);
+    let data = write_psb(&options, &PsbValue::Null, &ALIGNED, &[b"x", b"y"]);
+
+    assert_checksum(&data);
+    let resources = resource_positions(&data, false);
+    let extra = resource_positions(&data, true);
+    assert!(extra[0] > resources[3]);
+    assert_eq!(read_resource(&data, 2), ALIGNED[2]);
+    assert_eq!(read_extra_resource(&data, 0), b"x");
+    assert_eq!(read_extra_resource(&data, 1), b"y");
+
+    let before = write_psb(
+        &PsbWriterOptions::new(4),
+        &PsbValue::Null,
+        &ALIGNED,
+        &[b"x", b"y"],
+    );
+    assert_eq!(before.len(), data.len());
+    assert!(resource_positions(&before, true)[1] < resource_positions(&before, false)[0]);
+}
+
+#[test]
+fn custom_resource_order() {
+    let mut buf = Cursor::new(Vec::new());
+    let options = PsbWriterOptions::new(3).resource_dedup(ResourceDedup::ShareData);
+    let mut writer = PsbWriter::with_options(&options, &PsbValue::Null, &mut buf).unwrap();
+    for res in ALIGNED.into_iter().chain([&b"a"[..]]) {
+        writer.add_resource_data(res).unwrap();
+    }
+    writer.order_resources_by(|a, b| b.len.cmp(&a.len).then(a.index.cmp(&b.index)));
+    writer.finish().unwrap();
+    let data = buf.into_inner();
+
+    let positions = resource_positions(&data, false);
+    // Largest first, then "a" with the resource sharing it, "t" and the empty one.
+    assert!(positions[2] < positions[0]);
+    assert_eq!(positions[4], positions[0]);
+    assert!(positions[0] < positions[3]);
+    assert!(positions[3] <= positions[1]);
+    for (i, res) in ALIGNED.iter().enumerate() {
+        assert_eq!(read_resource(&data, i), *res);
+    }
+    assert_eq!(read_resource(&data, 4), b"a");
+}
+
+/// Seekable stream discarding written data.
+#[derive(Default)]
+struct Sink {
+    position: u64,
+    len: u64,
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.position += buf.len() as u64;
+        self.len = self.len.max(self.position);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for Sink {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = match pos {
+            SeekFrom::Start(position) => position,
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset).unwrap(),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset).unwrap(),
+        };
+        Ok(self.position)
+    }
+}
+
+#[test]
+fn offset_overflow() {
+    const LEN: u64 = 5 << 30;
+    let mut writer = PsbWriter::new(4, false, &PsbValue::Null, Sink::default()).unwrap();
+    writer
+        .add_extra_with(LEN, |out| {
+            let chunk = vec![0; 1 << 20];
+            for _ in 0..LEN / chunk.len() as u64 {
+                out.write_all(&chunk)?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    assert!(matches!(
+        writer.finish(),
+        Err(PsbWriteError::OffsetOverflow(offset)) if offset > LEN
+    ));
+}
+
+#[test]
+fn resource_dedup_data_and_stream() {
+    let options = PsbWriterOptions::new(3).resource_dedup(ResourceDedup::ReuseIndex);
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = PsbWriter::with_options(&options, &PsbValue::Null, &mut buf).unwrap();
+    let a = writer.add_resource(Cursor::new(b"same".to_vec())).unwrap();
+    let b = writer.add_resource_data(&b"same"[..]).unwrap();
+    assert_eq!(a, b);
+    assert_eq!(writer.resource_hash(a), Some(XxHash3_64::oneshot(b"same")));
+}
+
+/// A tree with many distinct names, with its entries inserted in an order picked
+/// by `rotation`: root entries are rotated by it, and child entries reversed when
+/// it is odd.
+fn many_names_tree(rotation: usize) -> PsbValue {
+    let mut root = IndexMap::new();
+    for i in (0..64).map(|i| (i + rotation as i64) % 64) {
+        let mut entries = [
+            (
+                SmolStr::new(format!("key{i}")),
+                PsbValue::String(format!("value{i}").into()),
+            ),
+            (
+                SmolStr::new(format!("{i}_suffix")),
+                PsbValue::Number(PsbNumber::Integer(i)),
+            ),
+        ];
+        if rotation % 2 == 1 {
+            entries.reverse();
+        }
+        root.insert(
+            SmolStr::new(format!("node_{i:02x}")),
+            PsbValue::Object(entries.into_iter().collect()),
+        );
+    }
+    PsbValue::Object(root)
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[test]
+fn deterministic_output() {
+    const CHILD_ENV: &str = "EMOTE_PSB_DETERMINISM_CHILD";
+
+    let options = PsbWriterOptions::new(4).dedup(true);
+    let expected = write_psb(&options, &many_names_tree(0), &[], &[]);
+    if std::env::var_os(CHILD_ENV).is_some() {
+        println!("psb:{}", to_hex(&expected));
+        return;
+    }
+
+    // Insertion order of the entries does not change the output.
+    for rotation in 1..32 {
+        assert_eq!(
+            write_psb(&options, &many_names_tree(rotation), &[], &[]),
+            expected,
+            "rotation {rotation}"
+        );
+    }
+
+    // Seeds of the hash maps used by the writer differ between processes.
+    for _ in 0..4 {
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "deterministic_output",
+                "--nocapture",
+                "--test-threads=1",
+            ])
+            .env(CHILD_ENV, "1")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let hex = stdout
+            .lines()
+            .find_map(|line| line.split_once("psb:").map(|(_, hex)| hex))
+            .unwrap();
+        assert_eq!(hex, to_hex(&expected));
+    }
+}
+
+#[test]
+fn large_name_table() {
+    // Archive-like file names, which used to take quadratic time to write.
+    let names = (0..60_000)
+        .map(|i| SmolStr::new(format!("image/chara{:03}/part_{i}.png", i % 700)))
+        .collect::<Vec<_>>();
+    let root = PsbValue::Object(
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.clone(), PsbValue::Number(PsbNumber::Integer(i as _))))
+            .collect(),
+    );
+
+    let data = write_psb(&PsbWriterOptions::new(3), &root, &[], &[]);
+    let mut psb = PsbFile::open(Cursor::new(data)).unwrap();
+
+    let mut sorted = names.clone();
+    sorted.sort_unstable();
+    assert!(psb.names.iter().eq(sorted.iter().map(SmolStr::as_str)));
+    assert_eq!(psb.deserialize_root::<PsbValue>().unwrap(), root);
+}
+
+fn names_tree(names: impl IntoIterator<Item = String>) -> PsbValue {
+    PsbValue::Object(
+        names
+            .into_iter()
+            .map(|name| (SmolStr::new(name), PsbValue::Null))
+            .collect(),
+    )
+}
+
+#[test]
+fn btree_first_fit_packing() {
+    let values = [
+        names_tree((0..2000).map(|i| format!("image/chara{:02}/part_{i}.png", i % 70))),
+        names_tree((0..500).map(|i| format!("{:x}", i * 7919))),
+        names_tree(["a", "ab", "abc", "b", "ほげ", "ほげほげ"].map(String::from)),
+        names_tree([]),
+    ];
+
+    for value in values {
+        let sequential = write_psb(&PsbWriterOptions::new(3), &value, &[], &[]);
+        let first_fit = write_psb(
+            &PsbWriterOptions::new(3).btree_packing(BtreePacking::FirstFit),
+            &value,
+            &[],
+            &[],
+        );
+        assert!(first_fit.len() <= sequential.len());
+
+        let sequential = PsbFile::open(Cursor::new(sequential)).unwrap();
+        let mut first_fit = PsbFile::open(Cursor::new(first_fit)).unwrap();
+        assert!(first_fit.names.iter().eq(sequential.names.iter()));
+        assert_eq!(first_fit.deserialize_root::<PsbValue>().unwrap(), value);
+    }
+}
+
+#[test]
+fn btree_first_fit_smaller() {
+    let value = names_tree((0..2000).map(|i| format!("motion/layer{:03}/{i}", i % 150)));
+    let sequential = write_psb(&PsbWriterOptions::new(3), &value, &[], &[]);
+    let first_fit = write_psb(
+        &PsbWriterOptions::new(3).btree_packing(BtreePacking::FirstFit),
+        &value,
+        &[],
+        &[],
+    );
+    assert!(first_fit.len() < sequential.len());
+}
+
+#[test]
+fn spooled_roundtrip() {
+    let mut root = IndexMap::new();
+    root.insert(SmolStr::new("tree"), repetitive_tree());
+    root.insert(
+        SmolStr::new("strings"),
+        PsbValue::List(
+            (0..300)
+                .map(|i| PsbValue::String(format!("s{}", i % 280).into()))
+                .collect(),
+        ),
+    );
+    root.insert(SmolStr::new("empty"), PsbValue::List(vec![]));
+    root.insert(SmolStr::new("nested"), PsbValue::Object(IndexMap::new()));
+    root.insert(
+        SmolStr::new("frames"),
+        PsbValue::IntArray((0..100).map(|i| i * 1000).collect()),
+    );
+    let values = [
+        PsbValue::Object(root),
+        PsbValue::Null,
+        PsbValue::String("root".into()),
+        PsbValue::List(vec![]),
+    ];
+
+    let options = PsbWriterOptions::new(3);
+    for value in values {
+        let data = write_psb(&options, Tree::Spooled(&value), &[], &[]);
+        assert_eq!(read_psb(data), value);
+    }
+}
+
+#[test]
+fn spooled_unused_resources_removed() {
+    let value = PsbValue::List(vec![
+        PsbValue::Resource(2),
+        PsbValue::List(vec![PsbValue::Resource(0)]),
+    ]);
+    let options = PsbWriterOptions::new(3).unused_resources(UnusedResources::Remove);
+    let data = write_psb(&options, Tree::Spooled(&value), &[b"a", b"b", b"c"], &[]);
+
+    assert_eq!(
+        read_psb(data.clone()),
+        PsbValue::List(vec![
+            PsbValue::Resource(1),
+            PsbValue::List(vec![PsbValue::Resource(0)]),
+        ])
+    );
+    assert_eq!(read_resource(&data, 0), b"a");
+    assert_eq!(read_resource(&data, 1), b"c");
+}
+
+/// Serializes a [`PsbValue`] through plain sequences and maps, keeping its lists
+/// and objects on the calling thread.
+#[cfg(feature = "rayon")]
+struct Sequential<'a>(&'a PsbValue);
+
+#[cfg(feature = "rayon")]
+impl Serialize for Sequential<'_> {
+    fn serialize<S: serde::Serializer>(&self, se: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{SerializeMap, SerializeSeq};
+
+        match self.0 {
+            PsbValue::List(list) => {
+                let mut seq = se.serialize_seq(Some(list.len()))?;
+                for value in list {
+                    seq.serialize_element(&Sequential(value))?;
+                }
+                seq.end()
+            }
+            PsbValue::Object(map) => {
+                let mut ser = se.serialize_map(Some(map.len()))?;
+                for (key, value) in map {
+                    ser.serialize_entry(key, &Sequential(value))?;
+                }
+                ser.end()
+            }
+            value => value.serialize(se),
+        }
+    }
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn parallel_output_identical() {
+    #[derive(Serialize)]
+    struct Model<T> {
+        name: &'static str,
+        value: T,
+    }
+
+    let entry = |i: i64| {
+        let mut map = IndexMap::new();
+        map.insert(SmolStr::new("id"), PsbValue::Number(PsbNumber::Integer(i)));
+        map.insert(
+            SmolStr::new(format!("name{}", i % 7)),
+            PsbValue::String(format!("entry {}", i % 90).into()),
+        );
+        map.insert(SmolStr::new("tree"), repetitive_tree());
+        map.insert(SmolStr::new("res"), PsbValue::Resource((i % 3) as u32));
+        PsbValue::Object(map)
+    };
+    let mut root = IndexMap::new();
+    for i in 0..100 {
+        root.insert(SmolStr::new(format!("key{i}")), entry(i % 40));
+    }
+    root.insert(
+        SmolStr::new("list"),
+        PsbValue::List((0..300).map(|i| entry(i % 50)).collect()),
+    );
+    let value = PsbValue::Object(root);
+
+    fn write(value: &impl Serialize, dedup: bool) -> Vec<u8> {
+        let mut buf = Buffer::new();
+        buf.set_dedup(dedup);
+        serialize(value, &mut buf).unwrap();
+        write_psb(
+            &PsbWriterOptions::new(3),
+            &mut buf,
+            &[b"a", b"b", b"c"],
+            &[],
+        )
+    }
+
+    for dedup in [false, true] {
+        assert_eq!(write(&value, dedup), write(&Sequential(&value), dedup));
+        assert_eq!(
+            write(
+                &Model {
+                    name: "model",
+                    value: &value
+                },
+                dedup
+            ),
+            write(
+                &Model {
+                    name: "model",
+                    value: Sequential(&value)
+                },
+                dedup
+            )
+        );
+    }
+}
+
+fn named_tree(names: &[&str], strings: &[&str]) -> PsbValue {
+    PsbValue::Object(
+        names
+            .iter()
+            .zip(strings.iter().cycle())
+            .map(|(&name, &string)| (SmolStr::new(name), PsbValue::String(string.into())))
+            .collect(),
+    )
+}
+
+#[test]
+fn buffer_reuse() {
+    let first = repetitive_tree();
+    let second = named_tree(&["b", "a"], &["y", "x"]);
+
+    let mut fresh = Buffer::new();
+    serialize(&second, &mut fresh).unwrap();
+
+    let mut buf = Buffer::new();
+    serialize(&first, &mut buf).unwrap();
+    buf.clear();
+    serialize(&second, &mut buf).unwrap();
+    assert_eq!(
+        write_psb(&PsbWriterOptions::new(3), &mut buf, &[], &[]),
+        write_psb(&PsbWriterOptions::new(3), &mut fresh, &[], &[])
+    );
+}
+
+#[test]
+fn dictionary_unused_entries() {
+    let names = ["alpha", "beta", "gamma", "delta"];
+    let strings = ["one", "two", "three"];
+    let value = named_tree(&["gamma", "alpha", "zeta"], &["two", "four"]);
+
+    let mut buf = Buffer::new();
+    buf.set_dictionary(names, strings);
+    serialize(&value, &mut buf).unwrap();
+    assert_eq!(
+        buf.names().iter().map(SmolStr::as_str).collect::<Vec<_>>(),
+        ["alpha", "beta", "delta", "gamma", "zeta"]
+    );
+    assert_eq!(
+        buf.used_names().map(SmolStr::as_str).collect::<Vec<_>>(),
+        ["alpha", "gamma", "zeta"]
+    );
+    assert_eq!(
+        buf.used_strings().map(SmolStr::as_str).collect::<Vec<_>>(),
+        ["four", "two"]
+    );
+
+    let data = write_psb(&PsbWriterOptions::new(3), &mut buf, &[], &[]);
+    let psb = PsbFile::open(Cursor::new(&data[..])).unwrap();
+    assert_eq!(psb.names.len(), 5);
+    assert_eq!(psb.strings.len(), 4);
+    assert_eq!(read_psb(data), value);
+
+    // The dictionary is restored for the next file.
+    buf.clear();
+    assert_eq!(buf.names().len(), 4);
+    assert_eq!(buf.strings().len(), 3);
+}
+
+#[test]
+fn dictionary_pruned() {
+    let values = [
+        named_tree(&["gamma", "alpha", "zeta"], &["two", "four"]),
+        named_tree(&["beta"], &["one"]),
+        repetitive_tree(),
+    ];
+
+    let mut buf = Buffer::new();
+    buf.set_dictionary(["alpha", "beta", "gamma", "x"], ["one", "two", "three"]);
+    buf.set_prune_unused(true);
+    for value in &values {
+        buf.clear();
+        serialize(value, &mut buf).unwrap();
+        assert_eq!(buf.used_names().count(), buf.names().len());
+
+        let mut fresh = Buffer::new();
+        serialize(value, &mut fresh).unwrap();
+        assert_eq!(
+            write_psb(&PsbWriterOptions::new(3), &mut buf, &[], &[]),
+            write_psb(&PsbWriterOptions::new(3), &mut fresh, &[], &[])
+        );
+    }
+}
+
+#[test]
+fn estimated_size() {
+    let mut root = IndexMap::new();
+    root.insert(SmolStr::new("tree"), repetitive_tree());
+    root.insert(
+        SmolStr::new("names"),
+        named_tree(&["alpha", "beta", "gamma"], &["one", "two", "three"]),
+    );
+    root.insert(
+        SmolStr::new("resources"),
+        PsbValue::List((0..3).map(PsbValue::Resource).collect()),
+    );
+    let value = PsbValue::Object(root);
+    let resources: [&[u8]; 3] = [b"abc", &[7; 300], b""];
+    let extra: [&[u8]; 2] = [b"extra", &[1; 1000]];
+    let lengths = |data: &[&[u8]]| data.iter().map(|d| d.len() as u64).collect::<Vec<_>>();
+
+    for version in [2, 3, 4] {
+        for alignment in [1, 16] {
+            for placement in [
+                ExtraPlacement::BeforeResources,
+                ExtraPlacement::AfterResources,
+            ] {
+                for packing in [BtreePacking::Sequential, BtreePacking::FirstFit] {
+                    let options = PsbWriterOptions::new(version)
+                        .resource_alignment(alignment)
+                        .extra_placement(placement)
+                        .btree_packing(packing);
+                    let extra: &[&[u8]] = if version > 3 { &extra } else { &[] };
+
+                    let mut buf = Buffer::new();
+                    serialize(&value, &mut buf).unwrap();
+                    let size = estimate_size_with_options(
+                        &options,
+                        &buf,
+                        &lengths(&resources),
+                        &lengths(extra),
+                    );
+
+                    let mut tree = Vec::new();
+                    buf.write(&mut tree).unwrap();
+                    assert_eq!(buf.encoded_len(), tree.len() as u64);
+
+                    let mut out = Cursor::new(Vec::new());
+                    let mut writer =
+                        PsbWriter::with_options_and_buffer(&options, &mut buf, &mut out).unwrap();
+                    for &data in &resources {
+                        writer.add_resource_data(data).unwrap();
+                    }
+                    for &data in extra {
+                        writer.add_extra_data(data).unwrap();
+                    }
+                    writer.finish().unwrap();
+
+                    let data = out.into_inner();
+                    assert_eq!(size.total(), data.len() as u64);
+                    assert_eq!(size.header, header_u32(&data, 8) as u64);
+                    assert_eq!(
+                        size.header + size.names,
+                        header_u32(&data, 36) as u64,
+                        "tree follows the names"
+                    );
+                }
+            }
+        }
+    }
+
+    let mut buf = Buffer::new();
+    serialize(&value, &mut buf).unwrap();
+    let size = estimate_size(3, &buf, &lengths(&resources));
+    let data = write_psb(&PsbWriterOptions::new(3), &value, &resources, &[]);
+    assert_eq!(size.total(), data.len() as u64);
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Sprite {
+    name: String,
+    image: PsbBytes,
+    mask: PsbBytes,
+}
+
+fn sprite(image: &[u8], mask: &[u8]) -> Sprite {
+    Sprite {
+        name: "sprite".into(),
+        image: PsbBytes(image.to_vec()),
+        mask: PsbBytes(mask.to_vec()),
+    }
+}
+
+#[test]
+fn bytes_resources_roundtrip() {
+    let value = sprite(b"image data", &[0xff; 300]);
+    let mut out = Cursor::new(Vec::new());
+    let mut writer = PsbWriter::new(3, false, &value, &mut out).unwrap();
+    assert_eq!(writer.add_resource_data(&b"attached"[..]).unwrap(), 2);
+    writer.finish().unwrap();
+    let data = out.into_inner();
+
+    let mut psb = PsbFile::open(Cursor::new(data.as_slice())).unwrap();
+    assert_eq!(psb.resources(), 3);
+    assert_eq!(psb.deserialize_root::<Sprite>().unwrap(), value);
+
+    let root = psb.deserialize_root::<PsbValue>().unwrap();
+    let PsbValue::Object(map) = root else {
+        panic!("expected an object, got {root:?}");
+    };
+    assert_eq!(map["image"], PsbValue::Resource(0));
+    assert_eq!(map["mask"], PsbValue::Resource(1));
+    assert_eq!(read_resource(&data, 0), b"image data");
+    assert_eq!(read_resource(&data, 2), b"attached");
+}
+
+#[test]
+fn bytes_resources_mixed_with_indices() {
+    #[derive(Serialize)]
+    struct Mixed {
+        first: PsbValue,
+        image: PsbBytes,
+        last: PsbValue,
+    }
+
+    let bytes = || PsbBytes(b"image".to_vec());
+    for value in [
+        Mixed {
+            first: PsbValue::Resource(0),
+            image: bytes(),
+            last: PsbValue::Null,
+        },
+        Mixed {
+            first: PsbValue::Null,
+            image: bytes(),
+            last: PsbValue::Resource(0),
+        },
+    ] {
+        let mut buf = Buffer::new();
+        assert!(matches!(
+            serialize(&value, &mut buf),
+            Err(ser::Error::MixedResources)
+        ));
+    }
+
+    // Extra resources are not collected, so they can still be referenced.
+    let value = Mixed {
+        first: PsbValue::ExtraResource(0),
+        image: bytes(),
+        last: PsbValue::Null,
+    };
+    let mut buf = Buffer::new();
+    serialize(&value, &mut buf).unwrap();
+}
+
+#[test]
+fn bytes_resources_dedup() {
+    let value = sprite(b"same", b"same");
+    // Collected resources keep their own index, attached ones may reuse it
+    for (dedup, attached, count) in [
+        (ResourceDedup::ReuseIndex, 0, 2),
+        (ResourceDedup::ShareData, 2, 3),
+    ] {
+        let options = PsbWriterOptions::new(3).resource_dedup(dedup);
+        let mut out = Cursor::new(Vec::new());
+        let mut writer = PsbWriter::with_options(&options, &value, &mut out).unwrap();
+        assert_eq!(writer.add_resource_data(&b"same"[..]).unwrap(), attached);
+        writer.finish().unwrap();
+        let data = out.into_inner();
+
+        let mut psb = PsbFile::open(Cursor::new(data.as_slice())).unwrap();
+        assert_eq!(psb.resources(), count);
+        assert_eq!(psb.deserialize_root::<Sprite>().unwrap(), value);
+
+        let positions = resource_positions(&data, false);
+        assert!(positions.iter().all(|&position| position == positions[0]));
+    }
+}
+
+#[test]
+fn bytes_resources_estimated_size() {
+    let value = sprite(b"image data", &[0xff; 300]);
+    let mut buf = Buffer::new();
+    serialize(&value, &mut buf).unwrap();
+    assert_eq!(buf.resources().len(), 2);
+    let size = estimate_size(3, &buf, &[8]);
+
+    let mut out = Cursor::new(Vec::new());
+    let mut writer = PsbWriter::new_with_buffer(3, false, &mut buf, &mut out).unwrap();
+    writer.add_resource_data(&b"attached"[..]).unwrap();
+    writer.finish().unwrap();
+    assert_eq!(size.total(), out.into_inner().len() as u64);
+}
@@ -0,0 +1,81 @@
+use emote_psb::psb::table::StringTable;
+use indexmap::IndexSet;
+use smol_str::SmolStr;
+
+fn table() -> StringTable {
+    ["bone", "", "body", "bone", "ab"].into_iter().collect()
+}
+
+#[test]
+fn table_len() {
+    let table = table();
+    assert_eq!(table.len(), 5);
+    assert_eq!(table.byte_len(), 14);
+    assert!(!table.is_empty());
+
+    let empty: StringTable = [""].into_iter().collect();
+    assert_eq!(empty.len(), 1);
+    assert!(!empty.is_empty());
+    assert!(StringTable::new().is_empty());
+}
+
+#[test]
+fn table_position() {
+    let mut table = table();
+    for indexed in [false, true] {
+        if indexed {
+            table.build_index();
+        }
+
+        assert_eq!(table.position("bone"), Some(0));
+        assert_eq!(table.position(""), Some(1));
+        assert_eq!(table.position("ab"), Some(4));
+        assert_eq!(table.position("b"), None);
+    }
+
+    assert_eq!(table.push_str("new"), 5);
+    assert_eq!(table.position("new"), Some(5));
+    assert_eq!(table.push_str("ab"), 6);
+    assert_eq!(table.position("ab"), Some(4));
+}
+
+#[test]
+fn table_binary_search() {
+    let table: StringTable = ["", "ab", "b", "body", "bone"].into_iter().collect();
+    assert_eq!(table.binary_search(""), Ok(0));
+    assert_eq!(table.binary_search("body"), Ok(3));
+    assert_eq!(table.binary_search("a"), Err(1));
+    assert_eq!(table.binary_search("z"), Err(5));
+}
+
+#[test]
+fn table_search() {
+    let table = table();
+    let found = |pattern| table.search(pattern).collect::<Vec<_>>();
+
+    assert_eq!(found("bo"), [(0, "bone"), (2, "body"), (3, "bone")]);
+    assert_eq!(found("o"), [(0, "bone"), (2, "body"), (3, "bone")]);
+    // Matches across entries are not reported.
+    assert!(found("eb").is_empty());
+    assert!(found("yb").is_empty());
+    assert_eq!(found("").len(), 5);
+}
+
+#[test]
+fn table_search_after_boundary_match() {
+    let table: StringTable = ["xa", "aa"].into_iter().collect();
+    // "aa" also occurs across "xa" and "aa", starting before the real match.
+    assert_eq!(table.search("aa").collect::<Vec<_>>(), [(1, "aa")]);
+}
+
+#[test]
+fn table_index_set_conversion() {
+    let set: IndexSet<SmolStr> = table().into();
+    assert_eq!(
+        set.iter().map(SmolStr::as_str).collect::<Vec<_>>(),
+        ["bone", "", "body", "ab"]
+    );
+
+    let table = StringTable::from(&set);
+    assert_eq!(table.iter().collect::<Vec<_>>(), ["bone", "", "body", "ab"]);
+}